@@ -1,9 +1,17 @@
 use std::io;
 
+pub const DEFAULT_LEVEL: i32 = 3;
+
 // compress binary data using zstd
 #[allow(dead_code)]
 pub fn compress(data: &Vec<u8>) -> Vec<u8> {
-    zstd::bulk::compress(&data[..], 3).unwrap()
+    compress_with_level(data, DEFAULT_LEVEL)
+}
+
+// compress binary data using zstd with the given compression level
+#[allow(dead_code)]
+pub fn compress_with_level(data: &[u8], level: i32) -> Vec<u8> {
+    zstd::bulk::compress(data, level).unwrap()
 }
 
 // decompress binary data using zstd
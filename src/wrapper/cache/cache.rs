@@ -2,43 +2,70 @@ use std::{path::Path, io};
 use crate::config;
 use cade::common::compression::zstd;
 
-use super::{provider::CacheProvider, file_provider::FileCacheProvider, redis_provider::RedisProvider};
+use super::{provider::CacheProvider, file_provider::FileCacheProvider, redis_provider::RedisProvider, http_provider::{self, HttpProvider}, s3_provider::{S3Credentials, S3Provider}, sqlite_provider::SqliteProvider, memcached_provider::MemcachedProvider, memory_provider::MemoryProvider, faulty_provider::FaultyProvider};
 
 /// This is a handler for caching.
 /// its purpose is to abstract the caching part from the rest of the logic
 pub struct Cache {
     providers: Vec<Box<dyn CacheProvider + 'static>>,
+    // access of the providers in the same order. Providers without write access are not migrated.
+    writable: Vec<bool>,
+    // never write to any provider, also not to back-fill hits or to remove faulty entries
+    readonly: bool,
+    compression_level: i32,
     debug: bool
 }
 
 impl Cache {
     pub fn new(config: &config::WrapperConfig) -> Option<Cache> {
-        let mut providers:Vec<Box<dyn CacheProvider + 'static>> = Vec::new();
-        let mut id = 0u32;
-        for cache_config in &config.cache {
-            match cache_config {
-                config::CacheConfig::filesystem(filesystem_config) => {
-                    let path = Path::new(&filesystem_config.path);
-                    let provider = FileCacheProvider::new(id.to_string(), path, filesystem_config.update_on_hit, config.panic_on_cache_content_mismatch, filesystem_config.test_if_update_is_required, config.debug);
-                    providers.push(Box::new(provider));
-                },
-                config::CacheConfig::redis(redis_config) => {
-                    let provider = RedisProvider::new(id.to_string(), &redis_config.url, redis_config.update_on_hit, config.panic_on_cache_content_mismatch, redis_config.expire, redis_config.test_if_update_is_required);
-                    providers.push(Box::new(provider));
-                }
-            }
-            id += 1;
-        }
+        let providers: Vec<Box<dyn CacheProvider + 'static>> = config.cache.iter()
+            .enumerate()
+            .map(|(id, cache_config)| Cache::create_provider(id.to_string(), cache_config, config))
+            .collect();
 
         if providers.len() == 0 {
             return None;
         }
         Some(Cache {
             providers: providers,
+            writable: config.cache.iter().map(config::CacheConfig::is_writable).collect(),
+            readonly: config.readonly,
+            compression_level: config.compression_level,
             debug: config.debug
         })
     }
 
+    fn create_provider(id: String, cache_config: &config::CacheConfig, config: &config::WrapperConfig) -> Box<dyn CacheProvider + 'static> {
+        match cache_config {
+            config::CacheConfig::filesystem(filesystem_config) => {
+                let path = Path::new(&filesystem_config.path);
+                Box::new(FileCacheProvider::new(id, path, filesystem_config.update_on_hit, config.panic_on_cache_content_mismatch, filesystem_config.test_if_update_is_required, filesystem_config.shard_levels, config.debug))
+            },
+            config::CacheConfig::redis(redis_config) => {
+                Box::new(RedisProvider::new(id, redis_config, config.panic_on_cache_content_mismatch))
+            },
+            config::CacheConfig::http(http_config) => {
+                Box::new(HttpProvider::new(id, http_config, config.panic_on_cache_content_mismatch, http_provider::authorization_from_env(), config.debug))
+            },
+            config::CacheConfig::s3(s3_config) => {
+                Box::new(S3Provider::new(id, s3_config, config.panic_on_cache_content_mismatch, S3Credentials::from_env(), config.debug))
+            },
+            config::CacheConfig::sqlite(sqlite_config) => {
+                Box::new(SqliteProvider::new(id, sqlite_config, config.panic_on_cache_content_mismatch, config.debug))
+            },
+            config::CacheConfig::memcached(memcached_config) => {
+                Box::new(MemcachedProvider::new(id, memcached_config, config.panic_on_cache_content_mismatch, config.debug))
+            },
+            config::CacheConfig::memory(memory_config) => {
+                Box::new(MemoryProvider::new(id, memory_config))
+            },
+            config::CacheConfig::faulty(faulty_config) => {
+                let inner = Cache::create_provider(id, &faulty_config.cache, config);
+                Box::new(FaultyProvider::new(inner, faulty_config, config.debug))
+            }
+        }
+    }
+
     pub fn get_entry(&self, category: Option<&str>, key: &str, provider_id: Option<&str>) -> io::Result<(Vec<u8>,&str)> {
         let mut found_faulty_entry = false;
         for provider in self.providers.iter() {
@@ -57,7 +84,7 @@ impl Cache {
                 match decompress_result {
                     Ok(decompressed_data) => {
                         for provider2 in self.providers.iter() {
-                            if id != provider2.get_id() && provider2.update() && (!provider2.test_if_update_is_required() || !provider2.has_entry(category, key) || found_faulty_entry) {
+                            if !self.readonly && id != provider2.get_id() && provider2.update() && (!provider2.test_if_update_is_required() || !provider2.has_entry(category, key) || found_faulty_entry) {
                                 if self.debug {
                                     eprintln!("Updating cache provider {} with category {} and key {}", provider2.get_id(), category.unwrap_or("None"), key);
                                 }
@@ -71,7 +98,9 @@ impl Cache {
                             eprintln!("Faulty entry found in cache provider {} with category {} and key {}", provider.get_id(), category.unwrap_or("None"), key);
                         }
                         // delete faulty entry and proceed with next provider
-                        provider.del_entry(category, key);
+                        if !self.readonly {
+                            provider.del_entry(category, key);
+                        }
                         found_faulty_entry = true;
                     }
                 }
@@ -81,6 +110,9 @@ impl Cache {
     }
 
     fn update_all_entry(&self, category: Option<&str>, key: &str, data: &Vec<u8>) {
+        if self.readonly {
+            return;
+        }
         // update in all caches
         for provider in self.providers.iter() {
             if provider.update() {
@@ -90,7 +122,193 @@ impl Cache {
     }
 
     pub fn set_entry(&self, category: Option<&str>, key: &str, data: &Vec<u8>) {
-        let compressed_data = zstd::compress(&data);
+        let compressed_data = zstd::compress_with_level(data, self.compression_level);
         self.update_all_entry(category, key, &compressed_data);
     }
-}
\ No newline at end of file
+
+    pub fn migrate(&self) -> io::Result<()> {
+        if self.readonly {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the caches are readonly"));
+        }
+        for (provider, writable) in self.providers.iter().zip(&self.writable) {
+            if !writable {
+                println!("Skipping cache provider {} without write access", provider.get_id());
+                continue;
+            }
+            let migrated = provider.migrate()?;
+            println!("Migrated {} entries of cache provider {}", migrated, provider.get_id());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::{Duration, Instant}};
+
+    use crate::config::{CacheAccess, CacheConfig, FaultyConfig, FilesystemConfig, MemoryConfig, WrapperConfig};
+
+    use super::*;
+
+    fn memory(update_on_hit: bool) -> CacheConfig {
+        CacheConfig::memory(MemoryConfig { access: CacheAccess::ReadWrite, update_on_hit, test_if_update_is_required: true })
+    }
+
+    fn faulty(fail_reads: bool, corrupt_reads: bool, delay_ms: u64) -> CacheConfig {
+        CacheConfig::faulty(FaultyConfig { cache: Box::new(memory(true)), fail_reads, fail_writes: false, corrupt_reads, delay_ms })
+    }
+
+    fn config(cache: Vec<CacheConfig>) -> WrapperConfig {
+        WrapperConfig {
+            base_dir: None,
+            path_map: Default::default(),
+            debug_prefix_map: true,
+            cache,
+            debug: false,
+            panic_on_cache_content_mismatch: false,
+            log: None,
+            compression_level: 3,
+            overrides: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_args: Vec::new(),
+            env_vars: Vec::new(),
+            sloppiness: Vec::new(),
+            disable: false,
+            readonly: false,
+            recache: false,
+            files: Vec::new()
+        }
+    }
+
+    fn create_cache(cache: Vec<CacheConfig>) -> Cache {
+        Cache::new(&config(cache)).unwrap()
+    }
+
+    #[test]
+    fn test_no_provider() {
+        assert!(Cache::new(&config(Vec::new())).is_none());
+    }
+
+    #[test]
+    fn test_set_entry() {
+        let cache = create_cache(vec![memory(true), memory(false)]);
+        cache.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+
+        // only providers accepting updates are written
+        assert!(cache.providers[0].has_entry(Some("obj"), "abc"));
+        assert!(!cache.providers[1].has_entry(Some("obj"), "abc"));
+        // entries are stored compressed
+        assert_eq!(zstd::decompress(&cache.providers[0].get_entry(Some("obj"), "abc").unwrap()).unwrap(), vec![1, 2, 3]);
+
+        let (data, id) = cache.get_entry(Some("obj"), "abc", None).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(id, "0");
+        assert!(cache.get_entry(Some("dep"), "abc", None).is_err());
+    }
+
+    #[test]
+    fn test_backfill() {
+        let cache = create_cache(vec![memory(true), memory(false), memory(true)]);
+        cache.providers[2].set_entry(Some("obj"), "abc", &zstd::compress(&vec![1, 2, 3]));
+
+        let (data, id) = cache.get_entry(Some("obj"), "abc", None).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(id, "2");
+
+        // the hit is copied to the providers accepting updates
+        assert!(cache.providers[0].has_entry(Some("obj"), "abc"));
+        assert!(!cache.providers[1].has_entry(Some("obj"), "abc"));
+        assert_eq!(cache.get_entry(Some("obj"), "abc", None).unwrap().1, "0");
+    }
+
+    #[test]
+    fn test_faulty_entry() {
+        let cache = create_cache(vec![faulty(false, true, 0), memory(true)]);
+        cache.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+
+        // the corrupt entry is skipped and deleted, then restored from the next provider
+        let (data, id) = cache.get_entry(Some("obj"), "abc", None).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(id, "1");
+        assert!(cache.providers[0].has_entry(Some("obj"), "abc"));
+
+        // without any intact copy the lookup fails and the corrupt entry is removed
+        let cache = create_cache(vec![faulty(false, true, 0)]);
+        cache.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+        assert!(cache.get_entry(Some("obj"), "abc", None).is_err());
+        assert!(!cache.providers[0].has_entry(Some("obj"), "abc"));
+    }
+
+    #[test]
+    fn test_failing_reads() {
+        let cache = create_cache(vec![faulty(true, false, 0), memory(true)]);
+        cache.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+
+        let (data, id) = cache.get_entry(Some("obj"), "abc", None).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(id, "1");
+    }
+
+    #[test]
+    fn test_provider_pinning() {
+        let cache = create_cache(vec![memory(true), memory(true)]);
+        cache.providers[0].set_entry(Some("obj"), "abc", &zstd::compress(&vec![1, 2, 3]));
+
+        assert!(cache.get_entry(Some("obj"), "abc", Some("1")).is_err());
+        assert!(cache.get_entry(Some("obj"), "abc", Some("2")).is_err());
+        assert_eq!(cache.get_entry(Some("obj"), "abc", Some("0")).unwrap().1, "0");
+        // a pinned hit still updates the other providers
+        assert_eq!(cache.get_entry(Some("obj"), "abc", Some("1")).unwrap().1, "1");
+    }
+
+    #[test]
+    fn test_slow_provider() {
+        let cache = create_cache(vec![faulty(false, false, 50)]);
+        cache.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+
+        let start = Instant::now();
+        assert_eq!(cache.get_entry(Some("obj"), "abc", None).unwrap().0, vec![1, 2, 3]);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_readonly() {
+        let mut cache = create_cache(vec![memory(true), memory(true)]);
+        cache.readonly = true;
+        cache.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+        assert!(!cache.providers[0].has_entry(Some("obj"), "abc"));
+
+        // hits are not copied to the other providers
+        cache.providers[1].set_entry(Some("obj"), "abc", &zstd::compress(&vec![1, 2, 3]));
+        assert_eq!(cache.get_entry(Some("obj"), "abc", None).unwrap().1, "1");
+        assert!(!cache.providers[0].has_entry(Some("obj"), "abc"));
+    }
+
+    #[test]
+    fn test_migrate_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let filesystem = |access| CacheConfig::filesystem(FilesystemConfig {
+            path: dir.path().to_str().unwrap().to_owned(),
+            access,
+            shard_levels: 1,
+            update_on_hit: true,
+            test_if_update_is_required: true
+        });
+        let flat_entry = dir.path().join("obj").join("abcdef");
+        fs::create_dir_all(flat_entry.parent().unwrap()).unwrap();
+        fs::write(&flat_entry, "data").unwrap();
+
+        // neither a cache without write access nor a readonly configuration is modified
+        create_cache(vec![filesystem(CacheAccess::Read)]).migrate().unwrap();
+        let mut cache = create_cache(vec![filesystem(CacheAccess::ReadWrite)]);
+        cache.readonly = true;
+        assert!(cache.migrate().is_err());
+        assert!(flat_entry.exists());
+
+        cache.readonly = false;
+        cache.migrate().unwrap();
+        assert!(!flat_entry.exists());
+        assert!(dir.path().join("obj").join("ab").join("abcdef").exists());
+    }
+}
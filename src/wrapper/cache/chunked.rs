@@ -0,0 +1,113 @@
+use std::io;
+
+use crate::hash;
+
+// marks an entry that does not contain the data itself but a list of chunks.
+// zstd frames start with a different magic number, so the marker cannot be mistaken for regular cache content.
+const MANIFEST_MAGIC: &str = "CADE_CHUNKED_V1\n";
+
+/// Manifest of an entry that was too large to be stored as a single value.
+/// It lists the keys of all chunks together with size and hash of the reassembled data.
+pub struct Manifest {
+    pub size: usize,
+    pub hash: String,
+    pub chunks: Vec<String>,
+}
+
+impl Manifest {
+    /// Splits `data` into chunks of at most `chunk_size` bytes.
+    /// The chunk keys are derived from `key` and the content hash, so stale chunks of a previous value are never reused.
+    pub fn split<'a>(key: &str, data: &'a [u8], chunk_size: usize) -> (Manifest, Vec<&'a [u8]>) {
+        let data_hash = hash::hash(data);
+        let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+        let manifest = Manifest {
+            size: data.len(),
+            chunks: (0..chunks.len()).map(|i| format!("{}_chunk_{}_{}", key, &data_hash[..16], i)).collect(),
+            hash: data_hash,
+        };
+        (manifest, chunks)
+    }
+
+    pub fn is_manifest(data: &[u8]) -> bool {
+        data.starts_with(MANIFEST_MAGIC.as_bytes())
+    }
+
+    pub fn magic_len() -> usize {
+        MANIFEST_MAGIC.len()
+    }
+
+    /// Returns `None` if `data` is not a manifest but a regular entry.
+    pub fn parse(data: &[u8]) -> Option<io::Result<Manifest>> {
+        if !Manifest::is_manifest(data) {
+            return None;
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid chunk manifest");
+        let parse = || -> io::Result<Manifest> {
+            let content = std::str::from_utf8(&data[MANIFEST_MAGIC.len()..]).map_err(|_| invalid())?;
+            let mut lines = content.lines();
+            let size = lines.next().and_then(|line| line.strip_prefix("size ")).and_then(|size| size.parse().ok()).ok_or_else(invalid)?;
+            let hash = lines.next().and_then(|line| line.strip_prefix("hash ")).ok_or_else(invalid)?.to_owned();
+            let chunks = lines.map(|line| line.to_owned()).collect();
+            Ok(Manifest { size, hash, chunks })
+        };
+        Some(parse())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut manifest = format!("{}size {}\nhash {}\n", MANIFEST_MAGIC, self.size, self.hash);
+        for chunk in &self.chunks {
+            manifest.push_str(chunk);
+            manifest.push('\n');
+        }
+        manifest.into_bytes()
+    }
+
+    /// Concatenates the chunks and verifies size and hash of the result.
+    pub fn assemble(&self, chunks: Vec<Vec<u8>>) -> io::Result<Vec<u8>> {
+        if chunks.len() != self.chunks.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "number of chunks does not match manifest"));
+        }
+        let data = chunks.concat();
+        if data.len() != self.size || hash::hash(&data) != self.hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "reassembled chunks do not match manifest"));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let (manifest, chunks) = Manifest::split("obj_abc", &data, 300);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(manifest.chunks.len(), 4);
+        assert!(manifest.chunks.iter().all(|chunk| chunk.starts_with("obj_abc_chunk_")));
+
+        let parsed = Manifest::parse(&manifest.to_bytes()).unwrap().unwrap();
+        assert_eq!(parsed.chunks, manifest.chunks);
+        assert_eq!(parsed.size, 1000);
+
+        let chunks = chunks.iter().map(|chunk| chunk.to_vec()).collect();
+        assert_eq!(parsed.assemble(chunks).unwrap(), data);
+    }
+
+    #[test]
+    fn test_regular_entry() {
+        assert!(Manifest::parse(b"\x28\xb5\x2f\xfd regular zstd data").is_none());
+    }
+
+    #[test]
+    fn test_corrupted_chunk() {
+        let data = vec![7u8; 100];
+        let (manifest, chunks) = Manifest::split("obj_abc", &data, 30);
+        let mut chunks: Vec<Vec<u8>> = chunks.iter().map(|chunk| chunk.to_vec()).collect();
+        chunks[2][0] = 8;
+        assert!(manifest.assemble(chunks.clone()).is_err());
+        chunks.pop();
+        assert!(manifest.assemble(chunks).is_err());
+    }
+}
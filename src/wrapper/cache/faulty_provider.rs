@@ -0,0 +1,85 @@
+use std::{io, thread, time::Duration};
+
+use crate::config::FaultyConfig;
+
+use super::provider::CacheProvider;
+
+/// Wrapper around another provider that injects failures.
+/// It is used to test how the cache handles unreachable, slow or corrupt providers.
+pub struct FaultyProvider {
+    inner: Box<dyn CacheProvider>,
+    fail_reads: bool,
+    fail_writes: bool,
+    corrupt_reads: bool,
+    delay: Duration,
+    debug: bool
+}
+
+impl FaultyProvider {
+    pub fn new(inner: Box<dyn CacheProvider>, config: &FaultyConfig, debug: bool) -> FaultyProvider {
+        FaultyProvider {
+            inner,
+            fail_reads: config.fail_reads,
+            fail_writes: config.fail_writes,
+            corrupt_reads: config.corrupt_reads,
+            delay: Duration::from_millis(config.delay_ms),
+            debug
+        }
+    }
+}
+
+impl CacheProvider for FaultyProvider {
+    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        thread::sleep(self.delay);
+        if self.fail_reads {
+            return Err(io::Error::other("injected read failure"));
+        }
+        let mut data = self.inner.get_entry(category, key)?;
+        if self.corrupt_reads {
+            if self.debug {
+                println!("Corrupting cache entry {}/{}", category.unwrap_or(""), key);
+            }
+            // flipping the first byte breaks the zstd frame header
+            if let Some(byte) = data.first_mut() {
+                *byte ^= 0xff;
+            }
+        }
+        Ok(data)
+    }
+
+    fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
+        thread::sleep(self.delay);
+        if self.fail_writes {
+            if self.debug {
+                println!("Dropping write of cache entry {}/{}", category.unwrap_or(""), key);
+            }
+            return;
+        }
+        self.inner.set_entry(category, key, value);
+    }
+
+    fn has_entry(&self, category: Option<&str>, key: &str) -> bool {
+        thread::sleep(self.delay);
+        !self.fail_reads && self.inner.has_entry(category, key)
+    }
+
+    fn update(&self) -> bool {
+        self.inner.update()
+    }
+
+    fn test_if_update_is_required(&self) -> bool {
+        self.inner.test_if_update_is_required()
+    }
+
+    fn get_id(&self) -> &str {
+        self.inner.get_id()
+    }
+
+    fn del_entry(&self, category: Option<&str>, key: &str) {
+        self.inner.del_entry(category, key);
+    }
+
+    fn migrate(&self) -> io::Result<usize> {
+        self.inner.migrate()
+    }
+}
@@ -1,7 +1,7 @@
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
-use super::provider::CacheProvider;
+use super::provider::{is_rewritten, CacheProvider};
 
 
 pub struct FileCacheProvider {
@@ -10,36 +10,111 @@ pub struct FileCacheProvider {
     update: bool,
     panic_on_cache_content_mismatch: bool,
     test_if_update_is_required: bool,
+    shard_levels: usize,
     debug: bool
 }
 
+// directories created by sharding are named after two hex digits of the key
+fn is_shard_dir(name: &str) -> bool {
+    name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 impl FileCacheProvider {
-    pub fn new(id: String, path: &Path, update: bool, panic_on_cache_content_mismatch: bool, test_if_update_is_required: bool, debug: bool) -> FileCacheProvider {
+    pub fn new(id: String, path: &Path, update: bool, panic_on_cache_content_mismatch: bool, test_if_update_is_required: bool, shard_levels: usize, debug: bool) -> FileCacheProvider {
         FileCacheProvider {
             id: id,
             path: path.to_path_buf(),
             update: update,
             panic_on_cache_content_mismatch: panic_on_cache_content_mismatch,
             test_if_update_is_required: test_if_update_is_required,
+            shard_levels,
             debug: debug
         }
     }
 
-    fn get_path(&self, category: Option<&str>, key: &str) -> PathBuf {
+    fn get_category_path(&self, category: Option<&str>) -> PathBuf {
         match category {
-            Some(category) => self.path.join(category).join(key),
-            None => self.path.join(key),
+            Some(category) => self.path.join(category),
+            None => self.path.clone(),
+        }
+    }
+
+    // e.g. <path>/obj/ab/cd/abcd... for two shard levels
+    fn get_path(&self, category: Option<&str>, key: &str) -> PathBuf {
+        let mut path = self.get_category_path(category);
+        if key.len() > 2 * self.shard_levels {
+            for level in 0..self.shard_levels {
+                path.push(&key[2 * level..2 * level + 2]);
+            }
+        }
+        path.join(key)
+    }
+
+    // location of entries written before sharding was enabled
+    fn get_flat_path(&self, category: Option<&str>, key: &str) -> PathBuf {
+        self.get_category_path(category).join(key)
+    }
+
+    fn collect_entries(dir: &Path, entries: &mut Vec<PathBuf>, recurse_all: bool) -> io::Result<()> {
+        for dir_entry in fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let file_type = dir_entry.file_type()?;
+            // files starting with a dot are kept by cade next to the entries (e.g. the header hash memo)
+            if dir_entry.file_name().to_str().is_some_and(|name| name.starts_with('.')) {
+                continue;
+            }
+            if file_type.is_file() {
+                entries.push(dir_entry.path());
+            } else if file_type.is_dir() && (recurse_all || is_shard_dir(dir_entry.file_name().to_str().unwrap_or(""))) {
+                FileCacheProvider::collect_entries(&dir_entry.path(), entries, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn migrate_category(&self, category: Option<&str>) -> io::Result<usize> {
+        let category_path = self.get_category_path(category);
+        let mut entries = Vec::new();
+        // without category the cache root also contains the category directories which must not be touched
+        FileCacheProvider::collect_entries(&category_path, &mut entries, category.is_some())?;
+
+        let mut migrated = 0;
+        for entry in entries {
+            let key = entry.file_name().unwrap().to_str().unwrap();
+            let target = self.get_path(category, key);
+            if target != entry {
+                if self.debug {
+                    println!("Moving cache entry {} to {}", entry.to_str().unwrap(), target.to_str().unwrap());
+                }
+                fs::create_dir_all(target.parent().unwrap())?;
+                fs::rename(&entry, &target)?;
+                migrated += 1;
+
+                // remove shard directories that became empty
+                let mut parent = entry.parent();
+                while let Some(dir) = parent {
+                    if dir == category_path || fs::remove_dir(dir).is_err() {
+                        break;
+                    }
+                    parent = dir.parent();
+                }
+            }
         }
+        Ok(migrated)
     }
 }
 
 impl CacheProvider for FileCacheProvider {
 
     fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        let path = self.get_path(category, key);
         if self.debug {
-            println!("Reading from cache: {}", self.get_path(category, key).to_str().unwrap());
+            println!("Reading from cache: {}", path.to_str().unwrap());
+        }
+        match fs::read(&path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound && self.shard_levels > 0 => fs::read(self.get_flat_path(category, key)),
+            result => result
         }
-        fs::read(self.get_path(category, key))
     }
 
     fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
@@ -47,7 +122,7 @@ impl CacheProvider for FileCacheProvider {
         if self.debug {
             println!("Writing to cache: {}", path.to_str().unwrap());
         }
-        if self.panic_on_cache_content_mismatch && path.exists() && category != Some("obj") {
+        if self.panic_on_cache_content_mismatch && path.exists() && category != Some("obj") && !is_rewritten(category) {
             let input_data = std::fs::read(&path).expect(&format!("Unable to read input file '{}'!", path.to_str().unwrap()));
             if input_data != *value {
                 panic!("content of '{}' does not match expected value! (hash collision?)", path.to_str().unwrap());
@@ -74,7 +149,7 @@ impl CacheProvider for FileCacheProvider {
         if self.debug {
             println!("Checking if cache entry exists: {}", path.to_str().unwrap());
         }
-        path.exists()
+        path.exists() || (self.shard_levels > 0 && self.get_flat_path(category, key).exists())
     }
 
     fn update(&self) -> bool {
@@ -91,5 +166,78 @@ impl CacheProvider for FileCacheProvider {
     
     fn del_entry(&self, category: Option<&str>, key: &str) {
         let _ = fs::remove_file(self.get_path(category, key));
+        if self.shard_levels > 0 {
+            let _ = fs::remove_file(self.get_flat_path(category, key));
+        }
+    }
+
+    fn migrate(&self) -> io::Result<usize> {
+        let mut migrated = 0;
+        if !self.path.exists() {
+            return Ok(migrated);
+        }
+        for dir_entry in fs::read_dir(&self.path)? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().to_str().unwrap_or("").to_owned();
+            if dir_entry.file_type()?.is_dir() && !is_shard_dir(&name) {
+                migrated += self.migrate_category(Some(&name))?;
+            }
+        }
+        migrated += self.migrate_category(None)?;
+        Ok(migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "abcdef0123456789";
+
+    #[test]
+    fn test_sharded_path() {
+        let provider = FileCacheProvider::new("0".to_owned(), Path::new("cache"), true, false, true, 2, false);
+        assert_eq!(provider.get_path(Some("obj"), KEY), Path::new("cache").join("obj").join("ab").join("cd").join(KEY));
+        assert_eq!(provider.get_path(None, KEY), Path::new("cache").join("ab").join("cd").join(KEY));
+    }
+
+    #[test]
+    fn test_read_flat_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let flat = FileCacheProvider::new("0".to_owned(), dir.path(), true, false, true, 0, false);
+        flat.set_entry(Some("obj"), KEY, &vec![1, 2, 3]);
+
+        let sharded = FileCacheProvider::new("0".to_owned(), dir.path(), true, false, true, 2, false);
+        assert!(sharded.has_entry(Some("obj"), KEY));
+        assert_eq!(sharded.get_entry(Some("obj"), KEY).unwrap(), vec![1, 2, 3]);
+        sharded.del_entry(Some("obj"), KEY);
+        assert!(!flat.has_entry(Some("obj"), KEY));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let flat = FileCacheProvider::new("0".to_owned(), dir.path(), true, false, true, 0, false);
+        flat.set_entry(Some("obj"), KEY, &vec![1]);
+        flat.set_entry(Some("dep"), KEY, &vec![2]);
+        flat.set_entry(None, KEY, &vec![3]);
+        // metadata next to the entries is not moved
+        fs::write(dir.path().join(".header_hashes"), "").unwrap();
+
+        let sharded = FileCacheProvider::new("0".to_owned(), dir.path(), true, false, true, 2, false);
+        assert_eq!(sharded.migrate().unwrap(), 3);
+        assert!(dir.path().join("obj").join("ab").join("cd").join(KEY).exists());
+        assert!(dir.path().join("dep").join("ab").join("cd").join(KEY).exists());
+        assert!(dir.path().join("ab").join("cd").join(KEY).exists());
+        assert!(!dir.path().join("obj").join(KEY).exists());
+        assert!(dir.path().join(".header_hashes").exists());
+        assert_eq!(sharded.migrate().unwrap(), 0);
+
+        // and back to the flat layout, removing the empty shard directories
+        assert_eq!(flat.migrate().unwrap(), 3);
+        assert_eq!(flat.get_entry(Some("obj"), KEY).unwrap(), vec![1]);
+        assert_eq!(flat.get_entry(None, KEY).unwrap(), vec![3]);
+        assert!(!dir.path().join("obj").join("ab").exists());
+        assert!(!dir.path().join("ab").exists());
+    }
+}
@@ -0,0 +1,225 @@
+use std::{env, io::{self, Read}, time::Duration};
+
+use base64::Engine;
+
+use crate::config::HttpConfig;
+
+use super::provider::{is_rewritten, CacheProvider};
+
+/// Provider for plain HTTP caches (nginx WebDAV, bazel-remote, Artifactory generic repositories).
+/// Entries are stored at `<url>/<category>/<key>`.
+pub struct HttpProvider {
+    id: String,
+    url: String,
+    update: bool,
+    panic_on_cache_content_mismatch: bool,
+    test_if_update_is_required: bool,
+    agent: ureq::Agent,
+    authorization: Option<String>,
+    debug: bool
+}
+
+/// Reads the credentials from the environment so they do not have to be stored in the config file.
+/// A bearer token (`CADE_HTTP_TOKEN`) takes precedence over basic auth (`CADE_HTTP_USERNAME`/`CADE_HTTP_PASSWORD`).
+pub fn authorization_from_env() -> Option<String> {
+    if let Ok(token) = env::var("CADE_HTTP_TOKEN") {
+        return Some(format!("Bearer {}", token));
+    }
+    if let Ok(username) = env::var("CADE_HTTP_USERNAME") {
+        let password = env::var("CADE_HTTP_PASSWORD").unwrap_or_default();
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        return Some(format!("Basic {}", credentials));
+    }
+    None
+}
+
+impl HttpProvider {
+    pub fn new(id: String, config: &HttpConfig, panic_on_cache_content_mismatch: bool, authorization: Option<String>, debug: bool) -> HttpProvider {
+        let timeout = Duration::from_secs(config.timeout);
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(timeout)
+            .timeout(timeout)
+            .build();
+
+        HttpProvider {
+            id,
+            url: config.url.trim_end_matches('/').to_owned(),
+            update: config.update_on_hit,
+            panic_on_cache_content_mismatch,
+            test_if_update_is_required: config.test_if_update_is_required,
+            agent,
+            authorization,
+            debug
+        }
+    }
+
+    fn get_url(&self, category: Option<&str>, key: &str) -> String {
+        match category {
+            Some(category) => format!("{}/{}/{}", self.url, category, key),
+            None => format!("{}/{}", self.url, key),
+        }
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request
+        }
+    }
+}
+
+fn to_io_error(err: ureq::Error) -> io::Error {
+    match err {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, "Not found"),
+        ureq::Error::Status(code, _) => io::Error::other(format!("http status {}", code)),
+        ureq::Error::Transport(transport) => io::Error::other(transport),
+    }
+}
+
+impl CacheProvider for HttpProvider {
+    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        let url = self.get_url(category, key);
+        if self.debug {
+            println!("Reading from cache: {}", url);
+        }
+        let response = self.request("GET", &url).call().map_err(to_io_error)?;
+        let mut data = Vec::new();
+        response.into_reader().read_to_end(&mut data)?;
+        if data.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Not found"));
+        }
+        Ok(data)
+    }
+
+    fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
+        let url = self.get_url(category, key);
+        if self.debug {
+            println!("Writing to cache: {}", url);
+        }
+        if self.panic_on_cache_content_mismatch && category != Some("obj") && !is_rewritten(category) {
+            if let Ok(input_data) = self.get_entry(category, key) {
+                if input_data != *value {
+                    panic!("content of '{}' does not match expected value! (hash collision?)", url);
+                }
+                return;
+            }
+        }
+        // a failing remote cache must not break the build
+        if let Err(err) = self.request("PUT", &url).send_bytes(value) {
+            eprintln!("Unable to write '{}': {}", url, to_io_error(err));
+        }
+    }
+
+    fn has_entry(&self, category: Option<&str>, key: &str) -> bool {
+        let url = self.get_url(category, key);
+        if self.debug {
+            println!("Checking if cache entry exists: {}", url);
+        }
+        self.request("HEAD", &url).call().is_ok()
+    }
+
+    fn update(&self) -> bool {
+        self.update
+    }
+
+    fn test_if_update_is_required(&self) -> bool {
+        self.test_if_update_is_required
+    }
+
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn del_entry(&self, category: Option<&str>, key: &str) {
+        let _ = self.request("DELETE", &self.get_url(category, key)).call();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::{Arc, Mutex}, thread};
+
+    use tiny_http::{Method, Response, Server};
+
+    use super::*;
+
+    type Entries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Minimal stand-in for a WebDAV server keeping all entries in memory.
+    /// Requests without the expected authorization header are rejected.
+    fn spawn_server(authorization: &'static str) -> (String, Entries) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cache", server.server_addr().to_ip().unwrap());
+        let entries: Entries = Arc::new(Mutex::new(HashMap::new()));
+        let server_entries = entries.clone();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let authorized = request.headers().iter().any(|header| header.field.equiv("Authorization") && header.value == authorization);
+                if !authorized {
+                    request.respond(Response::empty(401)).unwrap();
+                    continue;
+                }
+                let path = request.url().to_owned();
+                let mut entries = server_entries.lock().unwrap();
+                let response = match request.method() {
+                    Method::Get => match entries.get(&path) {
+                        Some(data) => Response::from_data(data.clone()),
+                        None => Response::from_data(Vec::new()).with_status_code(404),
+                    },
+                    Method::Head => Response::from_data(Vec::new()).with_status_code(if entries.contains_key(&path) { 200 } else { 404 }),
+                    Method::Put => {
+                        let mut data = Vec::new();
+                        request.as_reader().read_to_end(&mut data).unwrap();
+                        entries.insert(path, data);
+                        Response::from_data(Vec::new()).with_status_code(201)
+                    },
+                    Method::Delete => {
+                        entries.remove(&path);
+                        Response::from_data(Vec::new()).with_status_code(204)
+                    },
+                    _ => Response::from_data(Vec::new()).with_status_code(405),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        (url, entries)
+    }
+
+    fn http_config(url: &str) -> HttpConfig {
+        HttpConfig {
+            url: url.to_owned(),
+            access: crate::config::CacheAccess::ReadWrite,
+            timeout: 5,
+            update_on_hit: true,
+            test_if_update_is_required: true,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (url, entries) = spawn_server("Bearer secret");
+        let provider = HttpProvider::new("0".to_owned(), &http_config(&url), false, Some("Bearer secret".to_owned()), false);
+
+        assert!(!provider.has_entry(Some("obj"), "abc"));
+        assert_eq!(provider.get_entry(Some("obj"), "abc").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        provider.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+        assert!(entries.lock().unwrap().contains_key("/cache/obj/abc"));
+        assert!(provider.has_entry(Some("obj"), "abc"));
+        assert_eq!(provider.get_entry(Some("obj"), "abc").unwrap(), vec![1, 2, 3]);
+
+        provider.del_entry(Some("obj"), "abc");
+        assert!(!provider.has_entry(Some("obj"), "abc"));
+    }
+
+    #[test]
+    fn test_unauthorized() {
+        let (url, entries) = spawn_server("Bearer secret");
+        let provider = HttpProvider::new("0".to_owned(), &http_config(&url), false, Some("Basic dXNlcjpwYXNz".to_owned()), false);
+
+        provider.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+        assert!(entries.lock().unwrap().is_empty());
+        assert!(provider.get_entry(Some("obj"), "abc").is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use std::{io::{self, BufRead, BufReader, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::config::MemcachedConfig;
+
+use super::{chunked::Manifest, provider::{is_rewritten, CacheProvider}};
+
+// memcached interprets expiration times above 30 days as unix timestamps
+const MAX_RELATIVE_EXPIRE: u32 = 60 * 60 * 24 * 30;
+
+// number of points per server on the hash ring
+const VIRTUAL_NODES: usize = 100;
+
+/// Provider for one or more memcached servers using the text protocol.
+/// Keys are distributed over the servers by consistent hashing, so adding or removing a server only moves a fraction of the keys.
+pub struct MemcachedProvider {
+    id: String,
+    servers: Vec<String>,
+    ring: Vec<(u32, usize)>,
+    expire: Option<u32>,
+    item_size_limit: usize,
+    chunk_large_entries: bool,
+    timeout: Duration,
+    update: bool,
+    panic_on_cache_content_mismatch: bool,
+    test_if_update_is_required: bool,
+    debug: bool
+}
+
+fn ring_hash(data: &str) -> u32 {
+    let hash = blake3::hash(data.as_bytes());
+    u32::from_be_bytes(hash.as_bytes()[..4].try_into().unwrap())
+}
+
+struct MemcachedConnection {
+    reader: BufReader<TcpStream>,
+}
+
+impl MemcachedConnection {
+    fn open(server: &str, timeout: Duration) -> io::Result<MemcachedConnection> {
+        let address = server.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Unable to resolve '{}'", server)))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(MemcachedConnection { reader: BufReader::new(stream) })
+    }
+
+    fn send(&mut self, command: &[u8]) -> io::Result<()> {
+        self.reader.get_mut().write_all(command)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by memcached"));
+        }
+        Ok(line.trim_end().to_owned())
+    }
+
+    fn get(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.send(format!("get {}\r\n", key).as_bytes())?;
+        let line = self.read_line()?;
+        if line == "END" {
+            return Ok(None);
+        }
+        // VALUE <key> <flags> <bytes>
+        let size: usize = match line.split(' ').collect::<Vec<&str>>().as_slice() {
+            ["VALUE", _, _, size] => size.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, line.clone()))?,
+            _ => return Err(io::Error::other(line))
+        };
+        let mut data = vec![0; size + 2];
+        self.reader.read_exact(&mut data)?;
+        data.truncate(size);
+        let end = self.read_line()?;
+        if end != "END" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, end));
+        }
+        Ok(Some(data))
+    }
+
+    fn set(&mut self, key: &str, value: &[u8], exptime: u32) -> io::Result<()> {
+        let mut command = format!("set {} 0 {} {}\r\n", key, exptime, value.len()).into_bytes();
+        command.extend_from_slice(value);
+        command.extend_from_slice(b"\r\n");
+        self.send(&command)?;
+        match self.read_line()?.as_str() {
+            "STORED" => Ok(()),
+            response => Err(io::Error::other(response.to_owned()))
+        }
+    }
+
+    // returns false if the key does not exist
+    fn touch(&mut self, key: &str, exptime: u32) -> io::Result<bool> {
+        self.send(format!("touch {} {}\r\n", key, exptime).as_bytes())?;
+        match self.read_line()?.as_str() {
+            "TOUCHED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            response => Err(io::Error::other(response.to_owned()))
+        }
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<()> {
+        self.send(format!("delete {}\r\n", key).as_bytes())?;
+        match self.read_line()?.as_str() {
+            "DELETED" | "NOT_FOUND" => Ok(()),
+            response => Err(io::Error::other(response.to_owned()))
+        }
+    }
+}
+
+impl MemcachedProvider {
+    pub fn new(id: String, config: &MemcachedConfig, panic_on_cache_content_mismatch: bool, debug: bool) -> MemcachedProvider {
+        let mut ring = Vec::new();
+        for (server_index, server) in config.servers.iter().enumerate() {
+            for i in 0..VIRTUAL_NODES {
+                ring.push((ring_hash(&format!("{}-{}", server, i)), server_index));
+            }
+        }
+        ring.sort();
+
+        MemcachedProvider {
+            id,
+            servers: config.servers.clone(),
+            ring,
+            expire: config.expire,
+            item_size_limit: config.item_size_limit,
+            chunk_large_entries: config.chunk_large_entries,
+            timeout: Duration::from_secs(config.timeout),
+            update: config.update_on_hit,
+            panic_on_cache_content_mismatch,
+            test_if_update_is_required: config.test_if_update_is_required,
+            debug
+        }
+    }
+
+    fn get_key(&self, category: Option<&str>, key: &str) -> String {
+        match category {
+            Some(category) => format!("{}_{}", category, key),
+            None => key.to_string()
+        }
+    }
+
+    fn get_server(&self, full_key: &str) -> io::Result<&str> {
+        if self.ring.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no memcached servers configured"));
+        }
+        let hash = ring_hash(full_key);
+        let index = self.ring.partition_point(|(point, _)| *point < hash) % self.ring.len();
+        Ok(&self.servers[self.ring[index].1])
+    }
+
+    fn connect(&self, full_key: &str) -> io::Result<MemcachedConnection> {
+        MemcachedConnection::open(self.get_server(full_key)?, self.timeout)
+    }
+
+    fn exptime(&self) -> u32 {
+        match self.expire {
+            Some(expire) if expire > MAX_RELATIVE_EXPIRE => {
+                SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32 + expire
+            },
+            Some(expire) => expire,
+            None => 0
+        }
+    }
+
+    fn get_value(&self, full_key: &str) -> io::Result<Vec<u8>> {
+        let mut con = self.connect(full_key)?;
+        let data = con.get(full_key)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not found"))?;
+        if self.expire.is_some() {
+            con.touch(full_key, self.exptime())?;
+        }
+        Ok(data)
+    }
+
+    fn set_value(&self, full_key: &str, value: &[u8]) -> io::Result<()> {
+        self.connect(full_key)?.set(full_key, value, self.exptime())
+    }
+
+    fn get_manifest(&self, full_key: &str) -> Option<Manifest> {
+        let data = self.connect(full_key).ok()?.get(full_key).ok()??;
+        Manifest::parse(&data)?.ok()
+    }
+}
+
+impl CacheProvider for MemcachedProvider {
+    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        let full_key = self.get_key(category, key);
+        if self.debug {
+            println!("Reading from cache: {} ({})", full_key, self.get_server(&full_key).unwrap_or("-"));
+        }
+
+        let data = self.get_value(&full_key)?;
+        match Manifest::parse(&data) {
+            Some(manifest) => {
+                let result = manifest.and_then(|manifest| {
+                    let chunks = manifest.chunks.iter().map(|chunk_key| self.get_value(chunk_key)).collect::<io::Result<Vec<Vec<u8>>>>()?;
+                    manifest.assemble(chunks)
+                });
+                if result.is_err() {
+                    // chunks were evicted or are corrupt. Remove the entry so that it gets stored again.
+                    self.del_entry(category, key);
+                }
+                result
+            },
+            None => Ok(data)
+        }
+    }
+
+    fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
+        let full_key = self.get_key(category, key);
+        if self.debug {
+            println!("Writing to cache: {} ({})", full_key, self.get_server(&full_key).unwrap_or("-"));
+        }
+        if self.panic_on_cache_content_mismatch && category != Some("obj") && !is_rewritten(category) {
+            if let Ok(input_data) = self.get_entry(category, key) {
+                if input_data != *value {
+                    panic!("content of '{}' does not match expected value! (hash collision?)", full_key);
+                }
+                return;
+            }
+        }
+
+        let result = if value.len() <= self.item_size_limit {
+            self.set_value(&full_key, value)
+        } else if self.chunk_large_entries {
+            let (manifest, chunks) = Manifest::split(&full_key, value, self.item_size_limit);
+            // store the chunks first. The entry only becomes visible once the manifest is written.
+            manifest.chunks.iter().zip(chunks)
+                .try_for_each(|(chunk_key, chunk)| self.set_value(chunk_key, chunk))
+                .and_then(|_| self.set_value(&full_key, &manifest.to_bytes()))
+        } else {
+            if self.debug {
+                println!("Skipping {} with {} bytes, it exceeds the item size limit", full_key, value.len());
+            }
+            Ok(())
+        };
+        // a failing remote cache must not break the build
+        if let Err(err) = result {
+            eprintln!("Unable to write '{}' to memcached: {}", full_key, err);
+        }
+    }
+
+    fn has_entry(&self, category: Option<&str>, key: &str) -> bool {
+        let full_key = self.get_key(category, key);
+        // touching with the configured expiration also extends the lifetime of existing entries
+        self.connect(&full_key).and_then(|mut con| con.touch(&full_key, self.exptime())).unwrap_or(false)
+    }
+
+    fn update(&self) -> bool {
+        self.update
+    }
+
+    fn test_if_update_is_required(&self) -> bool {
+        self.test_if_update_is_required
+    }
+
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn del_entry(&self, category: Option<&str>, key: &str) {
+        let full_key = self.get_key(category, key);
+        if let Some(manifest) = self.get_manifest(&full_key) {
+            for chunk_key in &manifest.chunks {
+                let _ = self.connect(chunk_key).and_then(|mut con| con.delete(chunk_key));
+            }
+        }
+        let _ = self.connect(&full_key).and_then(|mut con| con.delete(&full_key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::TcpListener, sync::{Arc, Mutex}, thread};
+
+    use super::*;
+
+    type Entries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    const ITEM_SIZE_LIMIT: usize = 1024;
+
+    /// In-process stand-in for memcached implementing the commands used by the provider.
+    /// Like the real server it refuses items above the size limit.
+    fn spawn_server() -> (String, Entries) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let entries: Entries = Arc::new(Mutex::new(HashMap::new()));
+        let server_entries = entries.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let entries = server_entries.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        let parts: Vec<String> = line.split_whitespace().map(|part| part.to_owned()).collect();
+                        line.clear();
+                        let mut entries = entries.lock().unwrap();
+                        let response = match parts.iter().map(|part| part.as_str()).collect::<Vec<&str>>().as_slice() {
+                            ["get", key] => match entries.get(*key) {
+                                Some(data) => {
+                                    let mut response = format!("VALUE {} 0 {}\r\n", key, data.len()).into_bytes();
+                                    response.extend_from_slice(data);
+                                    response.extend_from_slice(b"\r\nEND\r\n");
+                                    response
+                                },
+                                None => b"END\r\n".to_vec()
+                            },
+                            ["set", key, _, _, size] => {
+                                let mut data = vec![0; size.parse::<usize>().unwrap() + 2];
+                                reader.read_exact(&mut data).unwrap();
+                                data.truncate(data.len() - 2);
+                                if data.len() > ITEM_SIZE_LIMIT {
+                                    b"SERVER_ERROR object too large for cache\r\n".to_vec()
+                                } else {
+                                    entries.insert(key.to_string(), data);
+                                    b"STORED\r\n".to_vec()
+                                }
+                            },
+                            ["touch", key, _] => if entries.contains_key(*key) { b"TOUCHED\r\n".to_vec() } else { b"NOT_FOUND\r\n".to_vec() },
+                            ["delete", key] => if entries.remove(*key).is_some() { b"DELETED\r\n".to_vec() } else { b"NOT_FOUND\r\n".to_vec() },
+                            _ => b"ERROR\r\n".to_vec()
+                        };
+                        reader.get_mut().write_all(&response).unwrap();
+                    }
+                });
+            }
+        });
+        (address, entries)
+    }
+
+    fn memcached_config(servers: Vec<String>, chunk_large_entries: bool) -> MemcachedConfig {
+        MemcachedConfig {
+            servers,
+            expire: Some(60 * 60 * 24 * 7),
+            item_size_limit: ITEM_SIZE_LIMIT,
+            chunk_large_entries,
+            timeout: 5,
+            access: crate::config::CacheAccess::ReadWrite,
+            update_on_hit: true,
+            test_if_update_is_required: true,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (server1, entries1) = spawn_server();
+        let (server2, entries2) = spawn_server();
+        let provider = MemcachedProvider::new("0".to_owned(), &memcached_config(vec![server1, server2], true), false, false);
+
+        for i in 0..32 {
+            let key = format!("key{}", i);
+            assert!(!provider.has_entry(Some("obj"), &key));
+            provider.set_entry(Some("obj"), &key, &vec![i; 16]);
+            assert!(provider.has_entry(Some("obj"), &key));
+            assert_eq!(provider.get_entry(Some("obj"), &key).unwrap(), vec![i; 16]);
+        }
+
+        // the keys are distributed over both servers
+        assert!(!entries1.lock().unwrap().is_empty());
+        assert!(!entries2.lock().unwrap().is_empty());
+
+        provider.del_entry(Some("obj"), "key0");
+        assert!(provider.get_entry(Some("obj"), "key0").is_err());
+    }
+
+    #[test]
+    fn test_consistent_hashing() {
+        let servers: Vec<String> = (0..4).map(|i| format!("server{}:11211", i)).collect();
+        let provider = MemcachedProvider::new("0".to_owned(), &memcached_config(servers.clone(), true), false, false);
+        let reduced = MemcachedProvider::new("0".to_owned(), &memcached_config(servers[..3].to_vec(), true), false, false);
+
+        // only keys of the removed server are moved
+        for i in 0..1000 {
+            let key = format!("obj_{}", i);
+            if provider.get_server(&key).unwrap() != servers[3] {
+                assert_eq!(provider.get_server(&key).unwrap(), reduced.get_server(&key).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_servers() {
+        let provider = MemcachedProvider::new("0".to_owned(), &memcached_config(Vec::new(), true), false, true);
+        provider.set_entry(Some("obj"), "key", &b"data".to_vec());
+        assert!(!provider.has_entry(Some("obj"), "key"));
+        assert_eq!(provider.get_entry(Some("obj"), "key").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_large_entries() {
+        let (server, entries) = spawn_server();
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+
+        let provider = MemcachedProvider::new("0".to_owned(), &memcached_config(vec![server.clone()], true), false, false);
+        provider.set_entry(Some("obj"), "large", &data);
+        assert_eq!(provider.get_entry(Some("obj"), "large").unwrap(), data);
+        assert_eq!(entries.lock().unwrap().len(), 6);
+
+        // an evicted chunk invalidates the whole entry
+        entries.lock().unwrap().retain(|key, _| !key.ends_with("_2"));
+        assert!(provider.get_entry(Some("obj"), "large").is_err());
+        assert!(entries.lock().unwrap().is_empty());
+
+        let skipping = MemcachedProvider::new("0".to_owned(), &memcached_config(vec![server], false), false, false);
+        skipping.set_entry(Some("obj"), "large", &data);
+        assert!(!skipping.has_entry(Some("obj"), "large"));
+    }
+}
@@ -0,0 +1,61 @@
+use std::{cell::RefCell, collections::HashMap, io};
+
+use crate::config::MemoryConfig;
+
+use super::provider::CacheProvider;
+
+/// Provider keeping all entries in memory for the lifetime of the process.
+/// It is meant for testing the interaction of several providers without any external storage.
+pub struct MemoryProvider {
+    id: String,
+    entries: RefCell<HashMap<(String, String), Vec<u8>>>,
+    update: bool,
+    test_if_update_is_required: bool,
+}
+
+impl MemoryProvider {
+    pub fn new(id: String, config: &MemoryConfig) -> MemoryProvider {
+        MemoryProvider {
+            id,
+            entries: RefCell::new(HashMap::new()),
+            update: config.update_on_hit,
+            test_if_update_is_required: config.test_if_update_is_required,
+        }
+    }
+
+    fn get_key(category: Option<&str>, key: &str) -> (String, String) {
+        (category.unwrap_or("").to_owned(), key.to_owned())
+    }
+}
+
+impl CacheProvider for MemoryProvider {
+    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        self.entries.borrow().get(&MemoryProvider::get_key(category, key))
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not found"))
+    }
+
+    fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
+        self.entries.borrow_mut().insert(MemoryProvider::get_key(category, key), value.clone());
+    }
+
+    fn has_entry(&self, category: Option<&str>, key: &str) -> bool {
+        self.entries.borrow().contains_key(&MemoryProvider::get_key(category, key))
+    }
+
+    fn update(&self) -> bool {
+        self.update
+    }
+
+    fn test_if_update_is_required(&self) -> bool {
+        self.test_if_update_is_required
+    }
+
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn del_entry(&self, category: Option<&str>, key: &str) {
+        self.entries.borrow_mut().remove(&MemoryProvider::get_key(category, key));
+    }
+}
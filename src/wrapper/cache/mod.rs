@@ -1,4 +1,11 @@
 pub(crate) mod cache;
 mod provider;
+mod chunked;
 mod file_provider;
-mod redis_provider;
\ No newline at end of file
+mod redis_provider;
+mod http_provider;
+mod s3_provider;
+mod sqlite_provider;
+mod memcached_provider;
+mod memory_provider;
+mod faulty_provider;
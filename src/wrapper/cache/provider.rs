@@ -1,5 +1,11 @@
 use std::io;
 
+/// Whether entries of `category` are replaced on purpose. The dependency manifest of a source file is rewritten
+/// whenever a dependency set is added, so a different content is no hash collision and has to be stored.
+pub fn is_rewritten(category: Option<&str>) -> bool {
+    category == Some("dep")
+}
+
 pub trait CacheProvider {
     fn get_id(&self) -> &str;
 
@@ -14,4 +20,10 @@ pub trait CacheProvider {
     fn update(&self) -> bool;
 
     fn test_if_update_is_required(&self) -> bool;
+
+    /// Rewrites existing entries to the currently configured storage layout.
+    /// Returns the number of moved entries.
+    fn migrate(&self) -> io::Result<usize> {
+        Ok(0)
+    }
 }
\ No newline at end of file
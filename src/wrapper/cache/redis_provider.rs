@@ -1,29 +1,119 @@
-use std::io;
+use std::{cell::RefCell, io};
 
-use redis::{Commands, Expiry, RedisError};
+use redis::{cluster::{ClusterClient, ClusterConnection}, sentinel::{SentinelClient, SentinelServerType}, Cmd, Commands, ConnectionLike, Expiry, RedisError, RedisResult, Value};
 
-use super::provider::CacheProvider;
+use crate::config::RedisConfig;
+
+use super::{chunked::Manifest, provider::{is_rewritten, CacheProvider}};
+
+enum RedisClient {
+    Single(redis::Client),
+    // the sentinel client needs to be mutable to query the current master
+    Sentinel(RefCell<SentinelClient>),
+    Cluster(ClusterClient),
+}
+
+enum RedisConnection {
+    Single(redis::Connection),
+    Cluster(Box<ClusterConnection>),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        match self {
+            RedisConnection::Single(con) => con.req_packed_command(cmd),
+            RedisConnection::Cluster(con) => con.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands(&mut self, cmd: &[u8], offset: usize, count: usize) -> RedisResult<Vec<Value>> {
+        match self {
+            RedisConnection::Single(con) => con.req_packed_commands(cmd, offset, count),
+            RedisConnection::Cluster(con) => con.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        match self {
+            RedisConnection::Single(con) => con.req_command(cmd),
+            RedisConnection::Cluster(con) => con.req_command(cmd),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConnection::Single(con) => con.get_db(),
+            RedisConnection::Cluster(con) => con.get_db(),
+        }
+    }
+
+    fn supports_pipelining(&self) -> bool {
+        match self {
+            RedisConnection::Single(con) => con.supports_pipelining(),
+            RedisConnection::Cluster(con) => con.supports_pipelining(),
+        }
+    }
+
+    fn check_connection(&mut self) -> bool {
+        match self {
+            RedisConnection::Single(con) => con.check_connection(),
+            RedisConnection::Cluster(con) => con.check_connection(),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        match self {
+            RedisConnection::Single(con) => con.is_open(),
+            RedisConnection::Cluster(con) => con.is_open(),
+        }
+    }
+}
+
+impl RedisClient {
+    fn open(config: &RedisConfig) -> RedisResult<RedisClient> {
+        match (&config.url, &config.sentinel, &config.cluster_nodes) {
+            (Some(url), None, None) => Ok(RedisClient::Single(redis::Client::open(url.as_str())?)),
+            (None, Some(sentinel), None) => {
+                let client = SentinelClient::build(sentinel.nodes.clone(), sentinel.master_name.clone(), None, SentinelServerType::Master)?;
+                Ok(RedisClient::Sentinel(RefCell::new(client)))
+            },
+            (None, None, Some(nodes)) => Ok(RedisClient::Cluster(ClusterClient::new(nodes.clone())?)),
+            _ => Err(RedisError::from((redis::ErrorKind::InvalidClientConfig, "exactly one of 'url', 'sentinel' or 'cluster_nodes' has to be set")))
+        }
+    }
+
+    fn get_connection(&self) -> RedisResult<RedisConnection> {
+        match self {
+            RedisClient::Single(client) => client.get_connection().map(RedisConnection::Single),
+            // the sentinels are asked for the current master on every connection, so failovers are followed
+            RedisClient::Sentinel(client) => client.borrow_mut().get_connection().map(RedisConnection::Single),
+            RedisClient::Cluster(client) => client.get_connection().map(|con| RedisConnection::Cluster(Box::new(con))),
+        }
+    }
+}
 
 pub struct RedisProvider {
     id: String,
     update: bool,
     panic_on_cache_content_mismatch: bool,
     expire: Option<u32>,
-    client: redis::Client,
+    chunk_size: usize,
+    client: RedisClient,
     test_if_update_is_required: bool
 }
 
 impl RedisProvider {
-    pub fn new(id: String, url: &str, update: bool, panic_on_cache_content_mismatch: bool, expire: Option<u32>, test_if_update_is_required: bool) -> RedisProvider {
-        let client = redis::Client::open(url).unwrap();
+    pub fn new(id: String, config: &RedisConfig, panic_on_cache_content_mismatch: bool) -> RedisProvider {
+        let client = RedisClient::open(config).unwrap();
 
         RedisProvider {
             id: id,
-            update: update,
+            update: config.update_on_hit,
             panic_on_cache_content_mismatch: panic_on_cache_content_mismatch,
-            expire: expire,
+            expire: config.expire,
+            chunk_size: config.chunk_size,
             client: client,
-            test_if_update_is_required: test_if_update_is_required
+            test_if_update_is_required: config.test_if_update_is_required
         }
     }
 
@@ -33,52 +123,96 @@ impl RedisProvider {
             None => key.to_string()
         }
     }
-}
 
-impl CacheProvider for RedisProvider {
-    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
-        let full_key = self.get_key(category, key);
-
-        let mut con = self.client.get_connection().unwrap();
-
-        let ret: Result<Vec<u8>, RedisError>;
-        if self.expire.is_some() {
-            ret = con.get_ex(&full_key, Expiry::EX(self.expire.unwrap().try_into().unwrap()));
-        } else {
-            ret = con.get(&full_key);
-        }
+    fn get_value(&self, con: &mut RedisConnection, full_key: &str) -> io::Result<Vec<u8>> {
+        let ret: Result<Vec<u8>, RedisError> = match self.expire {
+            Some(expire) => con.get_ex(full_key, Expiry::EX(expire.try_into().unwrap())),
+            None => con.get(full_key)
+        };
 
         match ret {
             Ok(data) => {
                 // retrun err if data is empty
-                if data.len() == 0 {
+                if data.is_empty() {
                     Err(io::Error::new(io::ErrorKind::NotFound, "Not found"))
                 } else {
                     Ok(data)
                 }
             },
-            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err))
+            Err(err) => Err(io::Error::other(err))
+        }
+    }
+
+    fn set_value(&self, con: &mut RedisConnection, full_key: &str, value: &[u8]) {
+        match self.expire {
+            Some(expire) => { let _:() = con.set_ex(full_key, value, expire.into()).unwrap(); },
+            None => { let _:() = con.set(full_key, value).unwrap(); }
+        }
+    }
+
+    // only fetches the whole value if it starts with the manifest marker
+    fn get_manifest(&self, con: &mut RedisConnection, full_key: &str) -> Option<Manifest> {
+        let prefix: Vec<u8> = con.getrange(full_key, 0, Manifest::magic_len() as isize - 1).ok()?;
+        if !Manifest::is_manifest(&prefix) {
+            return None;
+        }
+        let data: Vec<u8> = con.get(full_key).ok()?;
+        Manifest::parse(&data)?.ok()
+    }
+}
+
+impl CacheProvider for RedisProvider {
+    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        let full_key = self.get_key(category, key);
+
+        let mut con = self.client.get_connection().map_err(io::Error::other)?;
+
+        let data = self.get_value(&mut con, &full_key)?;
+        match Manifest::parse(&data) {
+            Some(manifest) => {
+                let result = manifest.and_then(|manifest| {
+                    let chunks = manifest.chunks.iter().map(|chunk_key| self.get_value(&mut con, chunk_key)).collect::<io::Result<Vec<Vec<u8>>>>()?;
+                    manifest.assemble(chunks)
+                });
+                if result.is_err() {
+                    // chunks are missing (e.g. expired) or corrupt. Remove the entry so that it gets stored again.
+                    self.del_entry(category, key);
+                }
+                result
+            },
+            None => Ok(data)
         }
     }
 
     fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
         let full_key = self.get_key(category, key);
         let mut con = self.client.get_connection().unwrap();
-        if self.has_entry(category, key) {
-            let _:() = con.expire(&full_key, self.expire.unwrap().into()).unwrap();
-            
+        // existing entries are content addressed, only their expiration is refreshed
+        if !is_rewritten(category) && self.has_entry(category, key) {
+            if let Some(expire) = self.expire {
+                let _:() = con.expire(&full_key, expire.into()).unwrap();
+                if let Some(manifest) = self.get_manifest(&mut con, &full_key) {
+                    for chunk_key in &manifest.chunks {
+                        let _:() = con.expire(chunk_key, expire.into()).unwrap();
+                    }
+                }
+            }
+
             if self.panic_on_cache_content_mismatch && category != Some("obj") {
                 let input_data = self.get_entry(category, key).expect(&format!("Unable to access redis key '{}'!", full_key));
                 if input_data != *value {
                     panic!("content of '{}' does not match expected value! (hash collision?)", full_key);
                 }
             }
-        } else {
-            if self.expire.is_some() {
-                let _:() = con.set_ex(&full_key, value, self.expire.unwrap().into()).unwrap();
-            } else {
-                let _:() = con.set(&full_key, value).unwrap();
+        } else if self.chunk_size > 0 && value.len() > self.chunk_size {
+            let (manifest, chunks) = Manifest::split(&full_key, value, self.chunk_size);
+            // store the chunks first. The entry only becomes visible once the manifest is written.
+            for (chunk_key, chunk) in manifest.chunks.iter().zip(chunks) {
+                self.set_value(&mut con, chunk_key, chunk);
             }
+            self.set_value(&mut con, &full_key, &manifest.to_bytes());
+        } else {
+            self.set_value(&mut con, &full_key, value);
         }
     }
 
@@ -99,9 +233,200 @@ impl CacheProvider for RedisProvider {
     fn get_id(&self) -> &str {
         self.id.as_str()
     }
-    
+
     fn del_entry(&self, category: Option<&str>, key: &str) {
+        let full_key = self.get_key(category, key);
         let mut con = self.client.get_connection().unwrap();
-        let _:() = con.del(self.get_key(category, key)).unwrap();
+        if let Some(manifest) = self.get_manifest(&mut con, &full_key) {
+            for chunk_key in &manifest.chunks {
+                let _:() = con.del(chunk_key).unwrap();
+            }
+        }
+        let _:() = con.del(full_key).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{TcpListener, TcpStream}, path::Path, process::{Child, Command, Stdio}, thread, time::Duration};
+
+    use crate::config::RedisSentinelConfig;
+
+    use super::*;
+
+    struct RedisServer {
+        process: Child,
+        port: u16,
+    }
+
+    impl Drop for RedisServer {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn spawn_redis(dir: &Path, extra_args: &[&str]) -> RedisServer {
+        let port = free_port();
+        let process = Command::new("redis-server")
+            .args(["--port", &port.to_string(), "--save", "", "--appendonly", "no"])
+            .args(extra_args)
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        wait_for_port(port);
+        RedisServer { process, port }
+    }
+
+    fn spawn_sentinel(dir: &Path, master_port: u16) -> RedisServer {
+        let port = free_port();
+        let conf = dir.join(format!("sentinel-{}.conf", port));
+        std::fs::write(&conf, format!("port {}\nsentinel monitor mymaster 127.0.0.1 {} 1\n", port, master_port)).unwrap();
+        let process = Command::new("redis-server")
+            .arg(&conf)
+            .arg("--sentinel")
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        wait_for_port(port);
+        RedisServer { process, port }
+    }
+
+    fn wait_for_port(port: u16) {
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("redis-server on port {} did not start", port);
     }
-}
\ No newline at end of file
+
+    fn redis_config(url: Option<String>, sentinel: Option<RedisSentinelConfig>, cluster_nodes: Option<Vec<String>>) -> RedisConfig {
+        RedisConfig {
+            url,
+            sentinel,
+            cluster_nodes,
+            expire: Some(60),
+            chunk_size: 1024,
+            access: crate::config::CacheAccess::ReadWrite,
+            update_on_hit: true,
+            test_if_update_is_required: true,
+        }
+    }
+
+    fn test_roundtrip(provider: &RedisProvider) {
+        // use several keys so that they end up on different cluster shards
+        for i in 0..16 {
+            let key = format!("key{}", i);
+            assert!(!provider.has_entry(Some("obj"), &key));
+            provider.set_entry(Some("obj"), &key, &vec![i; 16]);
+            assert!(provider.has_entry(Some("obj"), &key));
+            assert_eq!(provider.get_entry(Some("obj"), &key).unwrap(), vec![i; 16]);
+            provider.del_entry(Some("obj"), &key);
+            assert!(provider.get_entry(Some("obj"), &key).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(RedisClient::open(&redis_config(None, None, None)).is_err());
+        assert!(RedisClient::open(&redis_config(Some("redis://127.0.0.1".to_owned()), None, Some(vec!["redis://127.0.0.1".to_owned()]))).is_err());
+    }
+
+    #[test]
+    #[ignore = "requires redis-server in PATH, run with --ignored"]
+    fn test_single() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = spawn_redis(dir.path(), &[]);
+
+        let config = redis_config(Some(format!("redis://127.0.0.1:{}", server.port)), None, None);
+        test_roundtrip(&RedisProvider::new("0".to_owned(), &config, false));
+    }
+
+    #[test]
+    #[ignore = "requires redis-server in PATH, run with --ignored"]
+    fn test_chunked() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = spawn_redis(dir.path(), &[]);
+
+        let config = redis_config(Some(format!("redis://127.0.0.1:{}", server.port)), None, None);
+        let provider = RedisProvider::new("0".to_owned(), &config, false);
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        provider.set_entry(Some("obj"), "large", &data);
+        assert_eq!(provider.get_entry(Some("obj"), "large").unwrap(), data);
+
+        // the entry itself only holds the manifest
+        let mut con = redis::Client::open(format!("redis://127.0.0.1:{}", server.port)).unwrap().get_connection().unwrap();
+        let manifest: Vec<u8> = con.get("obj_large").unwrap();
+        let manifest = Manifest::parse(&manifest).unwrap().unwrap();
+        assert_eq!(manifest.chunks.len(), 10);
+
+        // a missing chunk invalidates the whole entry
+        let _: () = con.del(&manifest.chunks[3]).unwrap();
+        assert!(provider.get_entry(Some("obj"), "large").is_err());
+        assert!(!provider.has_entry(Some("obj"), "large"));
+        let remaining: bool = con.exists(&manifest.chunks[0]).unwrap();
+        assert!(!remaining);
+    }
+
+    #[test]
+    #[ignore = "requires redis-server in PATH, run with --ignored"]
+    fn test_sentinel() {
+        let dir = tempfile::tempdir().unwrap();
+        let master = spawn_redis(dir.path(), &[]);
+        let sentinel = spawn_sentinel(dir.path(), master.port);
+
+        let config = redis_config(None, Some(RedisSentinelConfig {
+            nodes: vec![format!("redis://127.0.0.1:{}", sentinel.port)],
+            master_name: "mymaster".to_owned(),
+        }), None);
+        let provider = RedisProvider::new("0".to_owned(), &config, false);
+        test_roundtrip(&provider);
+
+        // the entry has to be written to the master known by the sentinel
+        provider.set_entry(Some("obj"), "sentinel", &vec![1, 2, 3]);
+        let mut con = redis::Client::open(format!("redis://127.0.0.1:{}", master.port)).unwrap().get_connection().unwrap();
+        let data: Vec<u8> = con.get("obj_sentinel").unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    #[ignore = "requires redis-server in PATH, run with --ignored"]
+    fn test_cluster() {
+        let dir = tempfile::tempdir().unwrap();
+        let nodes: Vec<RedisServer> = (0..3).map(|i| {
+            spawn_redis(dir.path(), &["--cluster-enabled", "yes", "--cluster-config-file", &format!("nodes-{}.conf", i)])
+        }).collect();
+
+        // distribute the 16384 hash slots over all nodes and let them join
+        let slots_per_node = 16384 / nodes.len();
+        for (i, node) in nodes.iter().enumerate() {
+            let mut con = redis::Client::open(format!("redis://127.0.0.1:{}", node.port)).unwrap().get_connection().unwrap();
+            let first = i * slots_per_node;
+            let last = if i == nodes.len() - 1 { 16383 } else { first + slots_per_node - 1 };
+            let slots: Vec<usize> = (first..=last).collect();
+            let _: () = redis::cmd("CLUSTER").arg("ADDSLOTS").arg(slots).query(&mut con).unwrap();
+            let _: () = redis::cmd("CLUSTER").arg("MEET").arg("127.0.0.1").arg(nodes[0].port).query(&mut con).unwrap();
+        }
+        for node in &nodes {
+            let mut con = redis::Client::open(format!("redis://127.0.0.1:{}", node.port)).unwrap().get_connection().unwrap();
+            for _ in 0..100 {
+                let info: String = redis::cmd("CLUSTER").arg("INFO").query(&mut con).unwrap();
+                if info.contains("cluster_state:ok") {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        let config = redis_config(None, None, Some(nodes.iter().map(|node| format!("redis://127.0.0.1:{}", node.port)).collect()));
+        test_roundtrip(&RedisProvider::new("0".to_owned(), &config, false));
+    }
+}
@@ -0,0 +1,299 @@
+use std::{io, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::config::SqliteConfig;
+
+use super::provider::{is_rewritten, CacheProvider};
+
+// the total size is maintained by triggers, so size accounting does not need to scan the table
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        category TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        size INTEGER NOT NULL,
+        last_access INTEGER NOT NULL,
+        PRIMARY KEY (category, key)
+    );
+    CREATE INDEX IF NOT EXISTS entries_last_access ON entries (last_access);
+    CREATE TABLE IF NOT EXISTS stats (id INTEGER PRIMARY KEY CHECK (id = 0), total_size INTEGER NOT NULL);
+    INSERT OR IGNORE INTO stats (id, total_size) VALUES (0, 0);
+    CREATE TRIGGER IF NOT EXISTS entries_insert AFTER INSERT ON entries BEGIN
+        UPDATE stats SET total_size = total_size + new.size WHERE id = 0;
+    END;
+    CREATE TRIGGER IF NOT EXISTS entries_delete AFTER DELETE ON entries BEGIN
+        UPDATE stats SET total_size = total_size - old.size WHERE id = 0;
+    END;
+    CREATE TRIGGER IF NOT EXISTS entries_update AFTER UPDATE OF size ON entries BEGIN
+        UPDATE stats SET total_size = total_size - old.size + new.size WHERE id = 0;
+    END;
+";
+
+// the access time is only refreshed if it is older than this, to avoid a write on every hit
+const ACCESS_TIME_RESOLUTION: i64 = 60;
+
+/// Provider storing all entries in a single SQLite database.
+/// Several cade processes can access the same database concurrently.
+pub struct SqliteProvider {
+    id: String,
+    connection: Connection,
+    max_size: Option<u64>,
+    update: bool,
+    panic_on_cache_content_mismatch: bool,
+    test_if_update_is_required: bool,
+    debug: bool
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+fn to_io_error(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+impl SqliteProvider {
+    pub fn new(id: String, config: &SqliteConfig, panic_on_cache_content_mismatch: bool, debug: bool) -> SqliteProvider {
+        let path = Path::new(&config.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap_or_else(|_| panic!("Unable to create directory '{}'!", parent.to_str().unwrap()));
+        }
+        let connection = SqliteProvider::open(path, config.wal).unwrap_or_else(|err| panic!("Unable to open sqlite database '{}': {}", config.path, err));
+
+        SqliteProvider {
+            id,
+            connection,
+            max_size: config.max_size,
+            update: config.update_on_hit,
+            panic_on_cache_content_mismatch,
+            test_if_update_is_required: config.test_if_update_is_required,
+            debug
+        }
+    }
+
+    fn open(path: &Path, wal: bool) -> rusqlite::Result<Connection> {
+        let connection = Connection::open(path)?;
+        // wait for other cade processes instead of failing with SQLITE_BUSY
+        connection.busy_timeout(Duration::from_secs(60))?;
+        // WAL needs shared memory between the processes, which network filesystems do not provide
+        if wal {
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.pragma_update(None, "synchronous", "NORMAL")?;
+        } else {
+            connection.pragma_update(None, "journal_mode", "DELETE")?;
+        }
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
+    }
+
+    fn total_size(&self) -> rusqlite::Result<u64> {
+        self.connection.query_row("SELECT total_size FROM stats WHERE id = 0", [], |row| row.get(0))
+    }
+
+    /// Removes the least recently used entries until the total size is below `max_size`.
+    fn evict(&self, max_size: u64) -> rusqlite::Result<()> {
+        let transaction = rusqlite::Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        let total_size = self.total_size()?;
+        if total_size > max_size {
+            let mut to_free = total_size - max_size;
+            let mut victims = Vec::new();
+            {
+                let mut statement = transaction.prepare("SELECT category, key, size FROM entries ORDER BY last_access ASC")?;
+                let mut rows = statement.query([])?;
+                while to_free > 0 {
+                    match rows.next()? {
+                        Some(row) => {
+                            let size: u64 = row.get(2)?;
+                            victims.push((row.get::<_, String>(0)?, row.get::<_, String>(1)?));
+                            to_free = to_free.saturating_sub(size);
+                        },
+                        None => break
+                    }
+                }
+            }
+            for (category, key) in victims {
+                if self.debug {
+                    println!("Evicting from cache: {}/{}", category, key);
+                }
+                transaction.execute("DELETE FROM entries WHERE category = ?1 AND key = ?2", params![category, key])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+impl CacheProvider for SqliteProvider {
+    fn get_entry(&self, category: Option<&str>, key: &str) -> io::Result<Vec<u8>> {
+        let category = category.unwrap_or("");
+        if self.debug {
+            println!("Reading from cache: {}/{}", category, key);
+        }
+        let data: Option<Vec<u8>> = self.connection
+            .query_row("SELECT value FROM entries WHERE category = ?1 AND key = ?2", params![category, key], |row| row.get(0))
+            .optional()
+            .map_err(to_io_error)?;
+
+        match data {
+            Some(data) => {
+                let now = now();
+                // failing to record the access must not turn a hit into a miss
+                let _ = self.connection.execute("UPDATE entries SET last_access = ?3 WHERE category = ?1 AND key = ?2 AND last_access < ?4",
+                    params![category, key, now, now - ACCESS_TIME_RESOLUTION]);
+                Ok(data)
+            },
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "Not found"))
+        }
+    }
+
+    fn set_entry(&self, category: Option<&str>, key: &str, value: &Vec<u8>) {
+        if self.panic_on_cache_content_mismatch && category != Some("obj") && !is_rewritten(category) {
+            if let Ok(input_data) = self.get_entry(category, key) {
+                if input_data != *value {
+                    panic!("content of '{}/{}' does not match expected value! (hash collision?)", category.unwrap_or(""), key);
+                }
+                return;
+            }
+        }
+        let category = category.unwrap_or("");
+        if self.debug {
+            println!("Writing to cache: {}/{}", category, key);
+        }
+        self.connection.execute("INSERT INTO entries (category, key, value, size, last_access) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (category, key) DO UPDATE SET value = excluded.value, size = excluded.size, last_access = excluded.last_access",
+            params![category, key, value, value.len() as i64, now()])
+            .unwrap_or_else(|err| panic!("Unable to write '{}/{}' to sqlite database: {}", category, key, err));
+
+        if let Some(max_size) = self.max_size {
+            self.evict(max_size).unwrap_or_else(|err| panic!("Unable to evict entries from sqlite database: {}", err));
+        }
+    }
+
+    fn has_entry(&self, category: Option<&str>, key: &str) -> bool {
+        let category = category.unwrap_or("");
+        if self.debug {
+            println!("Checking if cache entry exists: {}/{}", category, key);
+        }
+        self.connection
+            .query_row("SELECT 1 FROM entries WHERE category = ?1 AND key = ?2", params![category, key], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+            .unwrap_or(false)
+    }
+
+    fn update(&self) -> bool {
+        self.update
+    }
+
+    fn test_if_update_is_required(&self) -> bool {
+        self.test_if_update_is_required
+    }
+
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn del_entry(&self, category: Option<&str>, key: &str) {
+        let _ = self.connection.execute("DELETE FROM entries WHERE category = ?1 AND key = ?2", params![category.unwrap_or(""), key]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn sqlite_config(path: &Path, max_size: Option<u64>) -> SqliteConfig {
+        SqliteConfig {
+            path: path.to_str().unwrap().to_owned(),
+            access: crate::config::CacheAccess::ReadWrite,
+            max_size,
+            update_on_hit: true,
+            test_if_update_is_required: true,
+            wal: false,
+        }
+    }
+
+    fn journal_mode(provider: &SqliteProvider) -> String {
+        provider.connection.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_journal_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = SqliteProvider::new("0".to_owned(), &sqlite_config(&dir.path().join("cache.db"), None), false, false);
+        assert_eq!(journal_mode(&provider), "delete");
+
+        let mut config = sqlite_config(&dir.path().join("local.db"), None);
+        config.wal = true;
+        let provider = SqliteProvider::new("0".to_owned(), &config, false, false);
+        assert_eq!(journal_mode(&provider), "wal");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = SqliteProvider::new("0".to_owned(), &sqlite_config(&dir.path().join("cache.db"), None), false, false);
+
+        assert!(!provider.has_entry(Some("obj"), "abc"));
+        assert!(provider.get_entry(Some("obj"), "abc").is_err());
+
+        provider.set_entry(Some("obj"), "abc", &vec![1, 2, 3]);
+        provider.set_entry(Some("dep"), "abc", &vec![4, 5]);
+        provider.set_entry(None, "abc", &vec![6]);
+        assert_eq!(provider.get_entry(Some("obj"), "abc").unwrap(), vec![1, 2, 3]);
+        assert_eq!(provider.get_entry(Some("dep"), "abc").unwrap(), vec![4, 5]);
+        assert_eq!(provider.get_entry(None, "abc").unwrap(), vec![6]);
+        assert_eq!(provider.total_size().unwrap(), 6);
+
+        // replacing an entry adjusts the total size
+        provider.set_entry(Some("obj"), "abc", &vec![1]);
+        assert_eq!(provider.total_size().unwrap(), 4);
+
+        provider.del_entry(Some("obj"), "abc");
+        assert!(!provider.has_entry(Some("obj"), "abc"));
+        assert_eq!(provider.total_size().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = SqliteProvider::new("0".to_owned(), &sqlite_config(&dir.path().join("cache.db"), Some(250)), false, false);
+
+        for i in 0..3 {
+            provider.set_entry(Some("obj"), &i.to_string(), &vec![0; 100]);
+            provider.connection.execute("UPDATE entries SET last_access = ?1 WHERE key = ?2", params![i, i.to_string()]).unwrap();
+        }
+
+        // the least recently used entry was removed
+        assert!(!provider.has_entry(Some("obj"), "0"));
+        assert!(provider.has_entry(Some("obj"), "1"));
+        assert!(provider.has_entry(Some("obj"), "2"));
+        assert_eq!(provider.total_size().unwrap(), 200);
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.db");
+
+        let threads: Vec<_> = (0..4).map(|i| {
+            let config = sqlite_config(&path, Some(1_000_000));
+            thread::spawn(move || {
+                let provider = SqliteProvider::new(i.to_string(), &config, false, false);
+                for j in 0..50 {
+                    let key = format!("{}_{}", i, j);
+                    provider.set_entry(Some("obj"), &key, &vec![i as u8; 100]);
+                    assert_eq!(provider.get_entry(Some("obj"), &key).unwrap(), vec![i as u8; 100]);
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let provider = SqliteProvider::new("0".to_owned(), &sqlite_config(&path, None), false, false);
+        assert_eq!(provider.total_size().unwrap(), 4 * 50 * 100);
+    }
+}
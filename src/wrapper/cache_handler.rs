@@ -11,6 +11,7 @@ pub trait CacheHandler {
     fn execute(&mut self, args: &Vec<String>) -> io::Result<Output> {
         let status = Command::new(&args[0])
             .args(&args[1..])
+            .args(self.get_extra_args())
             .output();
 
         self.execute_callback(&status);
@@ -20,6 +21,11 @@ pub trait CacheHandler {
 
     fn execute_callback(&mut self, _result: &io::Result<Output>) {}
 
+    /// Options appended to the arguments of the executable.
+    fn get_extra_args(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     fn resolve_tmpl(&self, tmpl: &str) -> String {
         tmpl.to_string()
     }
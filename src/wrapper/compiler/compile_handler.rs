@@ -1,8 +1,8 @@
-use std::{fs, io, path::Path, process::Output, str};
+use std::{env, fs, io, path::Path, process::Output, str};
 
-use crate::{cache_handler::CacheHandler, hash::Hasher, cache::cache::Cache, config};
+use crate::{cache_handler::CacheHandler, hash::Hasher, cache::cache::Cache, config::{self, Sloppiness}, path_map::{self, PathMap, BASE_DIR_NAME}};
 
-use super::{response_file, dep_parser::{self, DepParser}, gcc, tasking};
+use super::{response_file, dep_parser::{self, DepParser}, dep_manifest::{Candidate, DepManifest}, hash_memo::HashMemo, gcc, tasking, rules::CacheRules};
 
 
 pub trait CompilerTrait {
@@ -11,6 +11,32 @@ pub trait CompilerTrait {
     fn get_resp_file_prefix(&self) -> Vec<String>;
 
     fn get_dep_file_prefix(&self) -> Vec<String>;
+
+    /// Options setting the target of the dependency file (default: the output file).
+    fn get_dep_target_prefix(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Options making the compiler write `replacement` instead of `path` into debug information.
+    /// Empty if the compiler has no such option.
+    fn get_prefix_map_args(&self, _path: &str, _replacement: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Environment variables affecting the output of the compiler. A trailing `*` matches all variables with this prefix.
+    fn get_env_vars(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Environment variables making the compiler write additional files, which are not restored on a hit.
+    fn get_side_output_env_vars(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Prefixes of options remapping paths. Nothing is injected if the call already contains one of them.
+    fn get_prefix_map_prefixes(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 
@@ -20,21 +46,61 @@ pub struct Compiler<'a> {
     cache: Option<&'a Cache>,
     total_hash: Option<String>,
     dep_hash:Option<String>,
+    rules: CacheRules,
+    path_map: PathMap,
+    // false if a rule excludes this call from caching
+    cacheable: bool,
+    // options appended to the call of the compiler
+    extra_args: Vec<String>,
+    hash_memo: HashMemo,
     config: &'a config::WrapperConfig
 }
 
 struct CompilerArgs {
+    // arguments affecting the output, without the output locations (-o, dependency file and target)
     processed_args: Vec<String>,
     dep_file: Option<String>,
+    dep_target: Option<String>,
     out_file: Option<String>,
     source_file: Option<String>,
 }
 
+// the target of cached dependency files is replaced by the one of the current call on restore
+const DEP_TARGET_NAME: &str = "DEP_TARGET";
+
 impl<'a> CacheHandler for Compiler<'a> {
 
     fn cache_lookup(&mut self, args: &Vec<String>) -> Option<String> {
         self.parsed_args = self.parse_args(args);
 
+        if let (Some(source_file), Some(out_file)) = (&self.parsed_args.source_file, &self.parsed_args.out_file) {
+            // the arguments of the key, which include the options read from response files
+            let expanded_args: Vec<String> = self.parsed_args.processed_args.iter().map(|arg| self.path_map.resolve_placeholders(arg)).collect();
+            if let Some(reason) = self.rules.check(source_file, out_file, &expanded_args) {
+                if self.config.debug {
+                    println!("Not caching: {}", reason);
+                }
+                self.cacheable = false;
+                return None;
+            }
+        }
+
+        if let Some(name) = self.specific.get_side_output_env_vars().into_iter().find(|name| env::var_os(name).is_some()) {
+            if self.config.debug {
+                println!("Not caching: {} is set", name);
+            }
+            self.cacheable = false;
+            return None;
+        }
+
+        if self.cache.is_some() && self.config.debug_prefix_map {
+            self.extra_args = self.get_prefix_map_args();
+            // the injected options change the object file, so they are part of the key
+            for arg in &self.extra_args {
+                self.parsed_args.processed_args.push(self.path_map.insert_placeholders(arg));
+            }
+        }
+
         if let Some(cache) = self.cache {
             if self.parsed_args.dep_file.is_some() && self.parsed_args.out_file.is_some() && self.parsed_args.source_file.is_some() {
                 let source_file = self.parsed_args.source_file.as_ref().unwrap();
@@ -44,53 +110,49 @@ impl<'a> CacheHandler for Compiler<'a> {
                     Err(_) => { println!("Could not read source file {}.", source_file); std::process::exit(1); }
                 };
 
-                let mut dep_hasher = Hasher::new();
-                dep_hasher.update(&source_data);
-                self.update_hash(&mut dep_hasher);
-                self.dep_hash = Some(dep_hasher.finalize());
-
-                let dep_file = self.parsed_args.dep_file.as_ref().unwrap();
-
-                let dep_file_result = self.get_dep_file(cache);
-
-                match dep_file_result {
-                    Ok(dep_file_data) => {
-                        // an entry for the source file exists in the cache and we could restore its dependency file
-                        let mut dep_str = String::from_utf8(dep_file_data).unwrap();
-
-                        if self.config.base_dir.is_some() {
-                            // in case base dir is set, replace placeholder with actual base dir
-                            let base_dir = self.config.base_dir.as_ref().unwrap();
-                            dep_str = dep_str.replace("%%%BASE_DIR%%%", base_dir);
+                if let Some(time_macro) = find_time_macro(&source_data) {
+                    // the output changes with every call, unless the compiler takes the time from SOURCE_DATE_EPOCH (then it is part of the key)
+                    let fixed_time = env::var_os("SOURCE_DATE_EPOCH").is_some() && self.specific.get_env_vars().iter().any(|name| name == "SOURCE_DATE_EPOCH");
+                    if !self.config.is_sloppy(Sloppiness::TimeMacros) && !fixed_time {
+                        if self.config.debug {
+                            println!("Not caching: {} uses {}", source_file, time_macro);
                         }
-                        // write dep file to disk
-                        fs::write(dep_file, &dep_str).unwrap();
+                        self.cacheable = false;
+                        return None;
+                    }
+                }
 
-                        // parse dep file to create hash of all dependencies
-                        let dep = dep_parser::DepParser::new(&dep_str);
+                let mut dep_hasher = Hasher::new_key("dep");
+                dep_hasher.update_field(&source_data);
+                self.update_hash(&mut dep_hasher);
+                self.dep_hash = Some(dep_hasher.finalize());
 
-                        if let Some(hash) = self._get_object_hash(&dep).ok() {
-                            self.total_hash = Some(hash);
-                        } else {
-                            // error creating the hash (e.g. error reading dep file)
-                            return None;
-                        }
+                if self.config.recache {
+                    // treat every lookup as miss. The result is stored by cache_push.
+                    return None;
+                }
 
-                        let obj_result = cache.get_entry(Some("obj"), self.total_hash.as_ref().unwrap(), None);
-                        match obj_result {
-                            Ok((obj_data, provider_id)) => {
-                                // cache hit. Source file and all dependencies match.
-                                // write object file to disk
-                                fs::write(self.parsed_args.out_file.as_ref().unwrap(), obj_data).unwrap();
-                                return Some(provider_id.to_string());
-                            },
-                            // cache miss. Source file matches but dependencies not.
-                            Err(_) => ()
-                        }
-                    },
-                    // cache miss. C-File was updated.
-                    Err(_) => ()
-                };
+                // try all dependency sets stored for the source file, e.g. of other build configurations
+                for candidate in self.get_dep_manifest(cache).candidates {
+                    // replace placeholders with the actual paths (e.g. base dir) and the target of this call
+                    let dep_str = self.path_map.resolve_placeholders(&candidate.dep_file)
+                        .replace(&path_map::placeholder(DEP_TARGET_NAME), &self.get_dep_target());
+                    let dep = dep_parser::DepParser::new(&dep_str);
+
+                    let hash = match self._get_object_hash(&dep) {
+                        Ok(hash) => hash,
+                        // error creating the hash (e.g. a header of this candidate does not exist)
+                        Err(_) => continue
+                    };
+                    if let Ok((obj_data, provider_id)) = cache.get_entry(Some("obj"), &hash, None) {
+                        // cache hit. Source file and all dependencies match.
+                        // write dep file and object file to disk
+                        fs::write(self.parsed_args.dep_file.as_ref().unwrap(), &dep_str).unwrap();
+                        fs::write(self.parsed_args.out_file.as_ref().unwrap(), obj_data).unwrap();
+                        self.total_hash = Some(hash);
+                        return Some(provider_id.to_string());
+                    }
+                }
             }
         }
 
@@ -102,12 +164,15 @@ impl<'a> CacheHandler for Compiler<'a> {
     }
 
     fn cache_push(&mut self) {
+        if !self.cacheable {
+            return;
+        }
         if let Some(cache) = self.cache {
             if self.total_hash.is_none() {
                 let dep_file = self.parsed_args.dep_file.as_ref().unwrap();
                 match fs::read_to_string(dep_file) {
                     Ok(dep_str) => {
-                        let dep = dep_parser::DepParser::new(&dep_str);
+                        let mut dep = dep_parser::DepParser::new(&dep_str);
                         match self._get_object_hash(&dep) {
                             Ok(hash) => self.total_hash = Some(hash),
                             Err(err) => { 
@@ -116,16 +181,18 @@ impl<'a> CacheHandler for Compiler<'a> {
                             }
                         }
 
+                        // the output location is not part of the key, so the entry may be restored for another target
+                        dep.set_target(&path_map::placeholder(DEP_TARGET_NAME));
                         let mut dep_file_str = dep.get_dep_file_string();
 
-                        // replace base_dir with placeholder before caching
-                        // this is required to be able to set the propper base dir on cache load
-                        if self.config.base_dir.is_some() {
-                            let base_dir = self.config.base_dir.as_ref().unwrap();
-                            dep_file_str = dep_file_str.replace(base_dir, "%%%BASE_DIR%%%");
-                        }
-                        
-                        self.set_dep_file(&dep_file_str.as_bytes().to_vec(), cache);
+                        // replace base_dir and the other mapped paths with placeholders before caching
+                        // this is required to be able to set the propper paths on cache load
+                        dep_file_str = self.path_map.insert_placeholders(&dep_file_str);
+
+                        // read the manifest again, another build may have added a candidate in the meantime
+                        let mut manifest = self.get_dep_manifest(cache);
+                        manifest.add(Candidate { dep_file: dep_file_str });
+                        self.set_dep_file(&manifest.to_bytes(), cache);
                     },
                     Err(_) => {
                         println!("Could not read dep file {}.", dep_file);
@@ -148,6 +215,10 @@ impl<'a> CacheHandler for Compiler<'a> {
         self.total_hash.as_ref()
     }
 
+    fn get_extra_args(&self) -> Vec<String> {
+        self.extra_args.clone()
+    }
+
     fn resolve_tmpl(&self, tmpl: &str) -> String {
         let path_str = self.parsed_args.out_file.as_ref().unwrap();
         let path = Path::new(path_str);
@@ -158,6 +229,16 @@ impl<'a> CacheHandler for Compiler<'a> {
 
 }
 
+const TIME_MACROS: [&str; 3] = ["__DATE__", "__TIME__", "__TIMESTAMP__"];
+
+fn find_time_macro(data: &[u8]) -> Option<&'static str> {
+    TIME_MACROS.into_iter().find(|time_macro| data.windows(time_macro.len()).any(|window| window == time_macro.as_bytes()))
+}
+
+fn is_empty_dir(path: &str) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
 fn handle_path_arg(arg: &str, prefix:&str, next_arg: &Option<&String>) -> (bool, String) {
     let mut path =arg.strip_prefix(prefix).unwrap();
     let mut skip_next = false;
@@ -186,21 +267,33 @@ impl<'a> Compiler<'a> {
         }
         Compiler{
             specific: compiler,
-            parsed_args: CompilerArgs{processed_args: Vec::new(), dep_file: None, out_file: None, source_file: None},
+            parsed_args: CompilerArgs{processed_args: Vec::new(), dep_file: None, dep_target: None, out_file: None, source_file: None},
             cache: cache,
             total_hash: None,
             dep_hash: None,
+            rules: CacheRules::new(config),
+            path_map: PathMap::new(config),
+            cacheable: true,
+            extra_args: Vec::new(),
+            hash_memo: HashMemo::from_config(config),
             config: config
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_name(&self) -> String {
         self.specific.get_name()
     }
 
+    /// Determines the compiler family and the source file of a call before the cache is set up.
+    pub fn probe(exe_name: &str, args: &Vec<String>, config: &'a config::WrapperConfig) -> (String, Option<String>) {
+        let compiler = Compiler::new(exe_name, None, config);
+        let parsed_args = compiler.parse_args(args);
+        (compiler.get_name(), parsed_args.source_file)
+    }
+
     fn parse_args(&self, args: &Vec<String>) -> CompilerArgs {
         let mut dep_file = None;
+        let mut dep_target: Option<String> = None;
         let mut out_file = None;
         let mut source_file = None;
         
@@ -226,6 +319,7 @@ impl<'a> Compiler<'a> {
                     full_args.extend(resp_file_args.processed_args);
     
                     if dep_file == None { dep_file = resp_file_args.dep_file; }
+                    if dep_target.is_none() { dep_target = resp_file_args.dep_target; }
                     if out_file == None { out_file = resp_file_args.out_file; }
                     if source_file == None { source_file = resp_file_args.source_file; }
 
@@ -236,45 +330,83 @@ impl<'a> Compiler<'a> {
                 if arg.starts_with(&dep_file_prefix) {
                     let processed_arg;
                     (skip_next, processed_arg) = handle_path_arg(arg, &dep_file_prefix, &args.get(i+1));
-                    full_args.push(dep_file_prefix.to_owned() + &processed_arg);
                     dep_file = Some(processed_arg);
                     
                     continue 'arg_loop;
                 }
-            } if arg.starts_with("-I") || arg.starts_with("-c") || arg.starts_with("-o") {
+            }
+            for dep_target_prefix in self.specific.get_dep_target_prefix() {
+                if arg.starts_with(&dep_target_prefix) {
+                    let processed_arg;
+                    (skip_next, processed_arg) = handle_path_arg(arg, &dep_target_prefix, &args.get(i+1));
+                    // several targets are listed space separated
+                    dep_target = Some(match dep_target {
+                        Some(targets) => targets + " " + &processed_arg,
+                        None => processed_arg
+                    });
+
+                    continue 'arg_loop;
+                }
+            } if arg.starts_with("-I") || arg.starts_with("-D") || arg.starts_with("-c") || arg.starts_with("-o") {
                 let prefix = arg.get(0..2).unwrap();
                 let processed_arg;
                 (skip_next, processed_arg) = handle_path_arg(arg, prefix, &args.get(i+1));
+                if prefix == "-I" && self.config.is_sloppy(Sloppiness::EmptyIncludeDirs) && is_empty_dir(&processed_arg) {
+                    // nothing can be included from it, so it does not affect the output
+                    continue;
+                }
+                if prefix == "-o" {
+                    // the output location does not affect the output
+                    out_file = Some(processed_arg);
+                    continue;
+                }
                 full_args.push(prefix.to_owned() + &processed_arg);
 
                 if prefix == "-c" {
                     source_file = Some(processed_arg);
-                } else if prefix == "-o" {
-                    out_file = Some(processed_arg);
                 }
             } else {
                 full_args.push(arg.to_owned());
             }
         }
 
-        if let Some(base_dir) = self.config.base_dir.as_ref() {
-            for arg in &mut full_args {
-                *arg = arg.replace(base_dir, "");
-            }
+        for arg in &mut full_args {
+            *arg = self.path_map.insert_placeholders(arg);
         }
         
         CompilerArgs {
             processed_args: full_args,
             dep_file: dep_file,
+            dep_target,
             out_file: out_file,
             source_file: source_file,
         }
     }
     
-    fn get_dep_file(&self, cache: &Cache) -> io::Result<Vec<u8>> {
-        cache.get_entry(Some("dep"), &self.dep_hash.as_ref().unwrap(), None).and_then(|(dep_data, _)| {
-            Ok(dep_data)
-        })
+    /// Options replacing base_dir by `.` and the other mapped paths by `/<NAME>` in debug information,
+    /// each as configured and as resolved real path.
+    fn get_prefix_map_args(&self) -> Vec<String> {
+        let prefixes = self.specific.get_prefix_map_prefixes();
+        if self.parsed_args.processed_args.iter().any(|arg| prefixes.iter().any(|prefix| arg.starts_with(prefix))) {
+            // the user maps the paths on their own
+            return Vec::new();
+        }
+        let mut mappings: Vec<(&str, String)> = self.path_map.get_paths(BASE_DIR_NAME).into_iter().map(|path| (path, ".".to_owned())).collect();
+        for name in self.config.path_map.keys() {
+            mappings.extend(self.path_map.get_paths(name).into_iter().map(|path| (path, format!("/{}", name))));
+        }
+        // the compiler applies the last matching option, so a mapping nested in another one has to come later
+        mappings.sort_by_key(|(path, _)| path.len());
+        mappings.iter()
+            .flat_map(|(path, replacement)| self.specific.get_prefix_map_args(path, replacement))
+            .collect()
+    }
+
+    fn get_dep_manifest(&self, cache: &Cache) -> DepManifest {
+        match cache.get_entry(Some("dep"), &self.dep_hash.as_ref().unwrap(), None) {
+            Ok((dep_data, _)) => DepManifest::parse(&dep_data),
+            Err(_) => DepManifest::new()
+        }
     }
 
     pub fn set_dep_file(&self, data: &Vec<u8>, cache: &Cache) {
@@ -286,22 +418,89 @@ impl<'a> Compiler<'a> {
     }
 
     pub fn update_hash(&self, hasher: &mut Hasher) {
-        let mut all_args = self.parsed_args.processed_args.join("").to_string();
-        if let Some(base_dir) = self.config.base_dir.as_ref() {
-            all_args = all_args.replace(base_dir, "");
-        }
+        let args = self.get_canonical_args();
         if self.config.debug {
-            println!("all_args: {}", all_args);
+            println!("all_args: {}", args.join(" "));
+        }
+
+        hasher.update_field(&(args.len() as u64).to_le_bytes());
+        for arg in &args {
+            hasher.update_field(arg.as_bytes());
+        }
+
+        let env_vars = self.get_env_vars();
+        hasher.update_field(&(env_vars.len() as u64).to_le_bytes());
+        for (name, value) in env_vars {
+            if self.config.debug {
+                println!("env: {}={}", name, value);
+            }
+            hasher.update_field(name.as_bytes());
+            hasher.update_field(value.as_bytes());
+        }
+    }
+
+    /// Arguments as part of the keys. With the flag_order sloppiness, -D and -W options are sorted among each other.
+    fn get_canonical_args(&self) -> Vec<String> {
+        let mut args = self.parsed_args.processed_args.clone();
+        if self.config.is_sloppy(Sloppiness::FlagOrder) {
+            // macros and warnings are sorted by name. The sort is stable, so options for the same name
+            // (`-DA=1 -DA=2`, `-DA -UA`, `-Wfoo -Wno-foo`) keep their order, as the last one wins.
+            // Options passed on to other tools (-Wl,..., -Wa,...) keep their order.
+            let sort_key = |arg: &str| -> Option<(u8, String)> {
+                if arg.len() <= 2 {
+                    return None;
+                }
+                let (kind, name) = match &arg[..2] {
+                    "-D" | "-U" => (0, &arg[2..]),
+                    "-W" if !arg.contains(',') => (1, arg[2..].strip_prefix("no-").unwrap_or(&arg[2..])),
+                    _ => return None
+                };
+                Some((kind, name.split('=').next().unwrap_or_default().to_owned()))
+            };
+            let positions: Vec<usize> = (0..args.len()).filter(|i| sort_key(&args[*i]).is_some()).collect();
+            let mut flags: Vec<String> = positions.iter().map(|i| args[*i].clone()).collect();
+            flags.sort_by_cached_key(|flag| sort_key(flag));
+            for (i, flag) in positions.into_iter().zip(flags) {
+                args[i] = flag;
+            }
         }
+        args
+    }
+
+    /// Target of the dependency file as written by the compiler for this call.
+    fn get_dep_target(&self) -> String {
+        self.parsed_args.dep_target.clone().or_else(|| self.parsed_args.out_file.clone()).unwrap_or_default()
+    }
 
-        hasher.update(all_args.as_bytes());
+    /// Environment variables affecting the output with their values (mapped paths replaced by placeholders), sorted by name.
+    fn get_env_vars(&self) -> Vec<(String, String)> {
+        let patterns: Vec<String> = self.specific.get_env_vars().into_iter().chain(self.config.env_vars.iter().cloned()).collect();
+        let matches = |name: &str| patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern
+        });
+        let is_locale = |name: &str| name == "LANG" || name.starts_with("LC_");
+        let ignore_locale = self.config.is_sloppy(Sloppiness::Locale);
+        let mut env_vars: Vec<(String, String)> = env::vars()
+            .filter(|(name, _)| matches(name) && !(ignore_locale && is_locale(name)))
+            .map(|(name, value)| (name, self.path_map.insert_placeholders(&value)))
+            .collect();
+        env_vars.sort();
+        env_vars
     }
 
-    fn _get_object_hash(&self, dep: &DepParser) -> Result<String, Box<dyn std::error::Error + 'static>> {
+    fn _get_object_hash(&mut self, dep: &DepParser) -> Result<String, Box<dyn std::error::Error + 'static>> {
         // Hash an input incrementally.
-        let mut hasher = Hasher::new();
+        let mut hasher = Hasher::new_key("obj");
         self.update_hash(&mut hasher);
-        dep.update_hash(&mut hasher)?;
+        let result = dep.update_hash(&mut hasher, &mut self.hash_memo, &self.path_map);
+        // the memo only speeds up later calls, so failing to save it is not an error
+        if let Err(err) = self.hash_memo.save() {
+            if self.config.debug {
+                println!("Could not save header hashes: {}", err);
+            }
+        }
+        result?;
         let hash = hasher.finalize();
         Ok(hash)
     }
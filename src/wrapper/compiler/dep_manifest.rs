@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+// maximum number of dependency sets kept per source file and arguments. The oldest ones are dropped first.
+const MAX_CANDIDATES: usize = 8;
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// One dependency set seen for a source file, e.g. of a build configuration or branch.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Candidate {
+    // make dependency file with placeholders for the mapped paths
+    pub dep_file: String,
+}
+
+/// Content of the `dep` category: all dependency sets stored for a source file and its arguments, newest first.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DepManifest {
+    version: u32,
+    pub candidates: Vec<Candidate>,
+}
+
+impl DepManifest {
+    pub fn new() -> DepManifest {
+        DepManifest { version: MANIFEST_VERSION, candidates: Vec::new() }
+    }
+
+    /// Unreadable manifests are treated as empty.
+    pub fn parse(data: &[u8]) -> DepManifest {
+        match serde_json::from_slice::<DepManifest>(data) {
+            Ok(manifest) if manifest.version == MANIFEST_VERSION => manifest,
+            _ => DepManifest::new()
+        }
+    }
+
+    /// Adds `candidate` as the newest one, replacing a candidate with the same dependency file.
+    pub fn add(&mut self, candidate: Candidate) {
+        self.candidates.retain(|existing| *existing != candidate);
+        self.candidates.insert(0, candidate);
+        self.candidates.truncate(MAX_CANDIDATES);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str) -> Candidate {
+        Candidate { dep_file: format!("{}.o: {}.c", name, name) }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut manifest = DepManifest::new();
+        manifest.add(candidate("a"));
+        manifest.add(candidate("b"));
+        let manifest = DepManifest::parse(&manifest.to_bytes());
+        assert_eq!(manifest.candidates, vec![candidate("b"), candidate("a")]);
+
+        assert!(DepManifest::parse(b"a.o: \\\n a.c").candidates.is_empty());
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut manifest = DepManifest::new();
+        for i in 0..MAX_CANDIDATES + 2 {
+            manifest.add(candidate(&i.to_string()));
+        }
+        // adding a known dependency set again moves it to the front instead of duplicating it
+        manifest.add(candidate("5"));
+        assert_eq!(manifest.candidates.len(), MAX_CANDIDATES);
+        assert_eq!(manifest.candidates[0], candidate("5"));
+        assert_eq!(manifest.candidates[1], candidate("9"));
+        assert_eq!(manifest.candidates.iter().filter(|existing| **existing == candidate("5")).count(), 1);
+        assert!(!manifest.candidates.contains(&candidate("0")));
+    }
+}
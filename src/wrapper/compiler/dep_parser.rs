@@ -1,7 +1,9 @@
 
 use std::collections::BTreeSet;
 
-use crate::hash::Hasher;
+use crate::{hash::Hasher, path_map::{self, PathMap}};
+
+use super::hash_memo::HashMemo;
 
 pub struct DepParser {
     deps: Vec<String>,
@@ -40,11 +42,13 @@ impl DepParser {
                             val += el2;
                             if el2.ends_with('\\') {
                                 val += " ";
-                            } else  if !map.contains(&val.to_lowercase()) {
-                                self.deps.push(val.to_owned());
-                                map.insert(val.to_lowercase());
-                                val = "".to_owned();
                             } else {
+                                // the same header may be reached as `/ws/./a.h` and `/ws/a.h`
+                                val = path_map::normalize(&val);
+                                if !map.contains(&val.to_lowercase()) {
+                                    self.deps.push(val.to_owned());
+                                    map.insert(val.to_lowercase());
+                                }
                                 val = "".to_owned();
                             }
                         }
@@ -52,9 +56,10 @@ impl DepParser {
                     }
                 } else {
                     // escaped
-                    if is_prereq && !map.contains(&el.to_lowercase()) {
-                        self.deps.push(el.to_owned());
-                        map.insert(el.to_lowercase());
+                    let dep = path_map::normalize(el);
+                    if is_prereq && !map.contains(&dep.to_lowercase()) {
+                        map.insert(dep.to_lowercase());
+                        self.deps.push(dep);
                     }
                     column += el.len();
                 }
@@ -66,14 +71,23 @@ impl DepParser {
         // println!("{}", self.target);
     }
 
-    pub fn update_hash(&self, hasher: &mut Hasher) -> Result<(), Box<dyn std::error::Error + 'static>> {
-        for dep in &self.deps {
-            // dbg!("create hash for: {}", dep);
-            hasher.update(&std::fs::read(dep.replace("\\ ", " "))?);
+    /// Adds each dependency to the hash as path and digest of its content.
+    /// Mapped paths are replaced by their placeholders, so the hash does not depend on the location of the checkout.
+    pub fn update_hash(&self, hasher: &mut Hasher, hash_memo: &mut HashMemo, path_map: &PathMap) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let paths: Vec<String> = self.deps.iter().map(|dep| dep.replace("\\ ", " ")).collect();
+        let digests = hash_memo.hash_files(&paths)?;
+        hasher.update_field(&(self.deps.len() as u64).to_le_bytes());
+        for (dep, digest) in self.deps.iter().zip(digests) {
+            hasher.update_field(path_map.insert_placeholders(dep).as_bytes());
+            hasher.update_field(digest.as_bytes());
         }
         Ok(())
     }
 
+    pub fn set_target(&mut self, target: &str) {
+        self.target = target.to_owned();
+    }
+
     // create make dependency file from deps
     pub fn get_dep_file_string(&self) -> String {
         let mut dep_string = String::new();
@@ -173,4 +187,38 @@ mod tests {
         assert_eq!(dep_parser.deps, expected);
         assert_eq!(dep_parser.target, "a");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_normalize() {
+        let content = "a.o: /ws/./src/a.c /ws//inc/a.h \\
+ /ws/inc/a.h \"/ws/./b c.h\" ../inc/b.h";
+        let dep_parser = DepParser::new(content);
+        let expected = vec!["/ws/src/a.c", "/ws/inc/a.h", "/ws/b c.h", "../inc/b.h"];
+        assert_eq!(dep_parser.deps, expected);
+    }
+
+    #[test]
+    fn test_update_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkout = |name: &str| {
+            let checkout = dir.path().join(name);
+            for include_dir in ["a", "b"] {
+                std::fs::create_dir_all(checkout.join(include_dir)).unwrap();
+                std::fs::write(checkout.join(include_dir).join("x.h"), "same content").unwrap();
+            }
+            checkout.to_str().unwrap().to_owned()
+        };
+        let hash = |checkout: &str, header: &str| {
+            let path_map = PathMap::from_mappings([("BASE_DIR", &checkout.to_owned())].into_iter(), false);
+            let mut hasher = Hasher::new_key("obj");
+            DepParser::new(&format!("main.o: {}/{}", checkout, header)).update_hash(&mut hasher, &mut HashMemo::new(None), &path_map).unwrap();
+            hasher.finalize()
+        };
+
+        let first = checkout("first");
+        let second = checkout("second");
+        // the path of a header is part of the key, but not the location of the checkout
+        assert_ne!(hash(&first, "a/x.h"), hash(&first, "b/x.h"));
+        assert_eq!(hash(&first, "a/x.h"), hash(&second, "a/x.h"));
+    }
+}
@@ -14,4 +14,34 @@ impl CompilerTrait for Gcc {
     fn get_dep_file_prefix(&self) -> Vec<String> {
         vec!["-MF".to_owned()]
     }
+
+    fn get_dep_target_prefix(&self) -> Vec<String> {
+        vec!["-MT".to_owned(), "-MQ".to_owned()]
+    }
+
+    fn get_env_vars(&self) -> Vec<String> {
+        [
+            // include and library search paths
+            "CPATH", "C_INCLUDE_PATH", "CPLUS_INCLUDE_PATH", "OBJC_INCLUDE_PATH", "LIBRARY_PATH",
+            // location of the compiler passes
+            "GCC_EXEC_PREFIX", "COMPILER_PATH",
+            // value of __DATE__ and __TIME__
+            "SOURCE_DATE_EPOCH",
+            // language of the diagnostics
+            "LANG", "LC_*",
+        ].into_iter().map(str::to_owned).collect()
+    }
+
+    fn get_side_output_env_vars(&self) -> Vec<String> {
+        vec!["DEPENDENCIES_OUTPUT".to_owned(), "SUNPRO_DEPENDENCIES".to_owned()]
+    }
+
+    fn get_prefix_map_args(&self, path: &str, replacement: &str) -> Vec<String> {
+        // -ffile-prefix-map would also cover __FILE__, but is not supported by older (e.g. tricore) gcc versions
+        vec![format!("-fdebug-prefix-map={}={}", path, replacement)]
+    }
+
+    fn get_prefix_map_prefixes(&self) -> Vec<String> {
+        vec!["-fdebug-prefix-map=".to_owned(), "-ffile-prefix-map=".to_owned()]
+    }
 }
\ No newline at end of file
@@ -0,0 +1,276 @@
+use std::{collections::HashMap, fs::{self, Metadata}, io, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use rayon::prelude::*;
+
+use crate::{config::{CacheAccess, CacheConfig, Sloppiness, WrapperConfig}, hash};
+
+// stored in the directory of the first writable filesystem cache. Files starting with a dot are not cache entries.
+pub const MEMO_FILE_NAME: &str = ".header_hashes";
+
+// the memo is started over if it grows beyond this, keeping only the entries of the current call
+const MAX_ENTRIES: usize = 100_000;
+
+// a file modified within this time may be modified again without changing size and mtime (mtime resolution of 1 s or 2 s),
+// so its hash is not remembered
+const MIN_AGE: Duration = Duration::from_secs(2);
+
+#[derive(PartialEq, Clone, Debug)]
+struct FileStat {
+    size: u64,
+    // nanoseconds since the epoch
+    mtime: u128,
+    inode: u64,
+}
+
+impl FileStat {
+    fn new(metadata: &Metadata) -> FileStat {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        FileStat {
+            size: metadata.len(),
+            mtime: metadata.modified().ok().and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok()).map(|mtime| mtime.as_nanos()).unwrap_or(0),
+            inode
+        }
+    }
+}
+
+/// Remembers the hashes of headers across calls, so unchanged headers are not read again.
+/// A hash is reused as long as size, mtime and inode of the file are unchanged.
+pub struct HashMemo {
+    path: Option<PathBuf>,
+    loaded: bool,
+    entries: HashMap<String, (FileStat, String)>,
+    // paths hashed by this call which are not saved yet
+    added: Vec<String>,
+    min_age: Duration,
+    // only read the memo file, never write it (e.g. CADE_READONLY)
+    readonly: bool,
+}
+
+impl HashMemo {
+    /// Without `path` the hashes are only remembered for this call.
+    pub fn new(path: Option<PathBuf>) -> HashMemo {
+        HashMemo { path, loaded: false, entries: HashMap::new(), added: Vec::new(), min_age: MIN_AGE, readonly: false }
+    }
+
+    pub fn from_config(config: &WrapperConfig) -> HashMemo {
+        // caches without write access are often shared (e.g. on a network drive), the memo belongs to the local one
+        let mut hash_memo = HashMemo::new(config.cache.iter().find_map(|cache_config| match cache_config {
+            CacheConfig::filesystem(filesystem_config) if matches!(filesystem_config.access, CacheAccess::Write | CacheAccess::ReadWrite) => {
+                Some(PathBuf::from(&filesystem_config.path).join(MEMO_FILE_NAME))
+            },
+            _ => None
+        }));
+        hash_memo.readonly = config.readonly;
+        if config.is_sloppy(Sloppiness::IncludeFileMtime) {
+            hash_memo.min_age = Duration::ZERO;
+        }
+        hash_memo
+    }
+
+    fn read(&self) -> HashMap<String, (FileStat, String)> {
+        let mut entries = HashMap::new();
+        let content = match self.path.as_ref().map(fs::read_to_string) {
+            Some(Ok(content)) => content,
+            _ => return entries
+        };
+        for line in content.lines() {
+            // <hash> <size> <mtime> <inode> <path>
+            let fields: Vec<&str> = line.splitn(5, ' ').collect();
+            if let [hash, size, mtime, inode, path] = fields[..] {
+                if let (Ok(size), Ok(mtime), Ok(inode)) = (size.parse(), mtime.parse(), inode.parse()) {
+                    entries.insert(path.to_owned(), (FileStat { size, mtime, inode }, hash.to_owned()));
+                }
+            }
+        }
+        entries
+    }
+
+    /// Returns the stored hash of `path` or hashes its content.
+    /// The stat is returned if the new hash may be remembered.
+    fn hash_file(&self, path: &str) -> io::Result<(String, Option<FileStat>)> {
+        let metadata = fs::metadata(path)?;
+        let stat = FileStat::new(&metadata);
+        if let Some((known_stat, hash)) = self.entries.get(path) {
+            if *known_stat == stat {
+                return Ok((hash.clone(), None));
+            }
+        }
+
+        // the stat is taken before reading, so a modification while reading is detected by the next call
+        let hash = hash::hash_file(Path::new(path))?;
+        let old_enough = metadata.modified().ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .is_some_and(|age| age >= self.min_age);
+        Ok((hash, if old_enough && !path.contains('\n') { Some(stat) } else { None }))
+    }
+
+    /// Returns the hashes of the contents of the files at `paths` in the same order.
+    /// Files are hashed in parallel.
+    pub fn hash_files(&mut self, paths: &[String]) -> io::Result<Vec<String>> {
+        if !self.loaded {
+            self.entries = self.read();
+            self.loaded = true;
+        }
+
+        let results = paths.par_iter().map(|path| self.hash_file(path)).collect::<io::Result<Vec<_>>>()?;
+        let mut hashes = Vec::with_capacity(paths.len());
+        for (path, (hash, stat)) in paths.iter().zip(results) {
+            if let Some(stat) = stat {
+                self.entries.insert(path.to_owned(), (stat, hash.clone()));
+                self.added.push(path.to_owned());
+            }
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+
+    /// Writes the new hashes to the memo file.
+    /// Entries added by concurrent calls in the meantime are kept. The file is replaced atomically, so readers never see partial content.
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) if !self.readonly && !self.added.is_empty() && path.parent().is_some_and(|dir| dir.is_dir()) => path,
+            _ => return Ok(())
+        };
+
+        let mut entries = self.read();
+        if entries.len() + self.added.len() > MAX_ENTRIES {
+            entries.clear();
+        }
+        for added in self.added.drain(..) {
+            if let Some(entry) = self.entries.get(&added) {
+                entries.insert(added, entry.clone());
+            }
+        }
+
+        let mut content = String::new();
+        for (file, (stat, hash)) in &entries {
+            content.push_str(&format!("{} {} {} {} {}\n", hash, stat.size, stat.mtime, stat.inode, file));
+        }
+        let tmp_path = path.with_file_name(format!("{}.{}.tmp", MEMO_FILE_NAME, std::process::id()));
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path).inspect_err(|_| { let _ = fs::remove_file(&tmp_path); })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    fn memo(dir: &std::path::Path) -> HashMemo {
+        HashMemo::new(Some(dir.join(MEMO_FILE_NAME)))
+    }
+
+    fn write_old(path: &std::path::Path, content: &str) {
+        fs::write(path, content).unwrap();
+        File::options().write(true).open(path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+    }
+
+    #[test]
+    fn test_persistent() {
+        let dir = tempfile::tempdir().unwrap();
+        let header = dir.path().join("a.h");
+        let header_str = header.to_str().unwrap();
+        write_old(&header, "#define A 1\n");
+
+        let mut memo = self::memo(dir.path());
+        assert_eq!(memo.hash_files(&[header_str.to_owned()]).unwrap(), vec![hash::hash(b"#define A 1\n")]);
+        memo.save().unwrap();
+
+        // the stored hash is used as long as the file looks unchanged, even if the content differs
+        let mtime = fs::metadata(&header).unwrap().modified().unwrap();
+        fs::write(&header, "#define A 2\n").unwrap();
+        File::options().write(true).open(&header).unwrap().set_modified(mtime).unwrap();
+        assert_eq!(self::memo(dir.path()).hash_files(&[header_str.to_owned()]).unwrap(), vec![hash::hash(b"#define A 1\n")]);
+
+        // any change of the stat is detected
+        write_old(&header, "#define A 22\n");
+        assert_eq!(self::memo(dir.path()).hash_files(&[header_str.to_owned()]).unwrap(), vec![hash::hash(b"#define A 22\n")]);
+    }
+
+    #[test]
+    fn test_recently_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let header = dir.path().join("a.h");
+        fs::write(&header, "#define A 1\n").unwrap();
+
+        let mut memo = self::memo(dir.path());
+        memo.hash_files(&[header.to_str().unwrap().to_owned()]).unwrap();
+        memo.save().unwrap();
+        assert!(!dir.path().join(MEMO_FILE_NAME).exists());
+
+        // unless the include_file_mtime sloppiness is set
+        memo.min_age = Duration::ZERO;
+        memo.hash_files(&[header.to_str().unwrap().to_owned()]).unwrap();
+        memo.save().unwrap();
+        assert_eq!(self::memo(dir.path()).read().len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.h", "b.h"] {
+            write_old(&dir.path().join(name), name);
+        }
+
+        let mut first = memo(dir.path());
+        let mut second = memo(dir.path());
+        first.hash_files(&[dir.path().join("a.h").to_str().unwrap().to_owned()]).unwrap();
+        second.hash_files(&[dir.path().join("b.h").to_str().unwrap().to_owned()]).unwrap();
+        first.save().unwrap();
+        second.save().unwrap();
+
+        let entries = memo(dir.path()).read();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[dir.path().join("a.h").to_str().unwrap()].1, hash::hash(b"a.h"));
+    }
+
+    fn config(caches: &[(&std::path::Path, &str)], readonly: bool) -> WrapperConfig {
+        let caches: Vec<serde_json::Value> = caches.iter()
+            .map(|(path, access)| serde_json::json!({ "filesystem": { "path": path, "access": access } }))
+            .collect();
+        serde_json::from_value(serde_json::json!({ "cache": caches, "readonly": readonly, "sloppiness": ["include_file_mtime"] })).unwrap()
+    }
+
+    #[test]
+    fn test_write_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let header = dir.path().join("a.h");
+        fs::write(&header, "#define A 1\n").unwrap();
+        let hash = |config: &WrapperConfig| {
+            let mut memo = HashMemo::from_config(config);
+            memo.hash_files(&[header.to_str().unwrap().to_owned()]).unwrap();
+            memo.save().unwrap();
+        };
+
+        // neither caches without write access nor a readonly config are written to
+        hash(&config(&[(shared.path(), "Read"), (local.path(), "ReadWrite")], true));
+        assert_eq!(fs::read_dir(shared.path()).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(local.path()).unwrap().count(), 0);
+
+        // the memo goes to the first cache with write access
+        hash(&config(&[(shared.path(), "Read"), (local.path(), "ReadWrite")], false));
+        assert_eq!(fs::read_dir(shared.path()).unwrap().count(), 0);
+        assert!(local.path().join(MEMO_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = (0..4).map(|i| dir.path().join(format!("{}.h", i)).to_str().unwrap().to_owned()).collect();
+        let contents: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 3 * 1024 * 1024]).collect();
+        for (path, content) in paths.iter().zip(&contents) {
+            fs::write(path, content).unwrap();
+        }
+
+        // memory mapped and parallel hashing gives the same digests in the order of the paths
+        let digests = HashMemo::new(None).hash_files(&paths).unwrap();
+        assert_eq!(digests, contents.iter().map(|content| hash::hash(content)).collect::<Vec<_>>());
+    }
+}
@@ -2,4 +2,7 @@ pub(crate) mod compile_handler;
 pub(crate) mod gcc;
 pub(crate) mod tasking;
 mod response_file;
-mod dep_parser;
\ No newline at end of file
+mod dep_parser;
+mod dep_manifest;
+mod hash_memo;
+mod rules;
@@ -0,0 +1,102 @@
+use std::{env, path::Path};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::config::WrapperConfig;
+
+/// Decides which compiler calls are cached, based on the `include`, `exclude` and `exclude_args` globs of the config.
+pub struct CacheRules {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    exclude_args: GlobSet,
+    exclude_patterns: Vec<String>,
+    exclude_arg_patterns: Vec<String>,
+}
+
+pub fn build_glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+impl CacheRules {
+    pub fn new(config: &WrapperConfig) -> CacheRules {
+        // the patterns are checked when the config is loaded
+        CacheRules {
+            include: if config.include.is_empty() { None } else { Some(build_glob_set(&config.include).unwrap()) },
+            exclude: build_glob_set(&config.exclude).unwrap(),
+            exclude_args: build_glob_set(&config.exclude_args).unwrap(),
+            exclude_patterns: config.exclude.clone(),
+            exclude_arg_patterns: config.exclude_args.clone(),
+        }
+    }
+
+    /// Returns the reason why the call must not be cached, or `None` if it may be cached.
+    /// Paths are matched as given on the command line and as absolute path.
+    pub fn check(&self, source_file: &str, out_file: &str, args: &[String]) -> Option<String> {
+        let paths: Vec<String> = [source_file, out_file].iter()
+            .flat_map(|path| {
+                let absolute = env::current_dir().map(|dir| dir.join(path).to_string_lossy().into_owned()).unwrap_or_default();
+                [path.to_string(), absolute]
+            })
+            .collect();
+
+        if let Some(include) = &self.include {
+            if !paths.iter().any(|path| include.is_match(Path::new(path))) {
+                return Some(format!("neither '{}' nor '{}' matches an include rule", source_file, out_file));
+            }
+        }
+        for path in &paths {
+            if let Some(index) = self.exclude.matches(Path::new(path)).first() {
+                return Some(format!("'{}' matches exclude rule '{}'", path, self.exclude_patterns[*index]));
+            }
+        }
+        for arg in args {
+            if let Some(index) = self.exclude_args.matches(arg).first() {
+                return Some(format!("argument '{}' matches exclude rule '{}'", arg, self.exclude_arg_patterns[*index]));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(include: &[&str], exclude: &[&str], exclude_args: &[&str]) -> CacheRules {
+        let to_vec = |patterns: &[&str]| patterns.iter().map(|pattern| pattern.to_string()).collect::<Vec<String>>();
+        CacheRules {
+            include: if include.is_empty() { None } else { Some(build_glob_set(&to_vec(include)).unwrap()) },
+            exclude: build_glob_set(&to_vec(exclude)).unwrap(),
+            exclude_args: build_glob_set(&to_vec(exclude_args)).unwrap(),
+            exclude_patterns: to_vec(exclude),
+            exclude_arg_patterns: to_vec(exclude_args),
+        }
+    }
+
+    #[test]
+    fn test_no_rules() {
+        assert_eq!(rules(&[], &[], &[]).check("src/main.c", "build/main.o", &["-O2".to_owned()]), None);
+    }
+
+    #[test]
+    fn test_path_rules() {
+        let rules = rules(&["**/src/**"], &["**/generated/*.c", "*/version.o"], &[]);
+        assert_eq!(rules.check("src/main.c", "build/main.o", &[]), None);
+        assert!(rules.check("/project/src/main.c", "/project/build/main.o", &[]).is_none());
+        assert!(rules.check("lib/main.c", "build/main.o", &[]).unwrap().contains("include rule"));
+        assert!(rules.check("src/generated/build_info.c", "build/build_info.o", &[]).unwrap().contains("'**/generated/*.c'"));
+        assert!(rules.check("src/version.c", "build/version.o", &[]).unwrap().contains("'*/version.o'"));
+    }
+
+    #[test]
+    fn test_argument_rules() {
+        let rules = rules(&[], &[], &["-fprofile-generate*", "-DBUILD_TIME=*"]);
+        assert_eq!(rules.check("main.c", "main.o", &["-O2".to_owned(), "-fprofile-use".to_owned()]), None);
+        assert!(rules.check("main.c", "main.o", &["-fprofile-generate=/tmp".to_owned()]).unwrap().contains("-fprofile-generate*"));
+        assert!(rules.check("main.c", "main.o", &["-DBUILD_TIME=1234".to_owned()]).is_some());
+    }
+}
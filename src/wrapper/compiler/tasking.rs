@@ -1,5 +1,6 @@
 use super::compile_handler::CompilerTrait;
 
+// The TASKING tools have no option to remap paths in debug information, so no prefix map is injected.
 pub struct Tasking;
 
 impl CompilerTrait for Tasking {
@@ -14,4 +15,9 @@ impl CompilerTrait for Tasking {
     fn get_dep_file_prefix(&self) -> Vec<String> {
         vec!["--dep-file=".to_owned()]
     }
+
+    fn get_env_vars(&self) -> Vec<String> {
+        // include search path and licensing
+        ["CTCINC", "TSK_LICENSE_KEY_*", "TSK_LICENSE_SERVER"].into_iter().map(str::to_owned).collect()
+    }
 }
\ No newline at end of file
@@ -1,7 +1,68 @@
+use std::{collections::BTreeMap, env, fmt, fs, path::{Path, PathBuf}};
+
 use config::FileFormat;
-use serde::Deserialize;
+use redis::IntoConnectionInfo;
+use serde::{Deserialize, Serialize};
+
+// name of the project config file, searched in the current and all parent directories
+const PROJECT_CONFIG_NAME: &str = ".cade";
+// name of the user and system config file
+const CONFIG_NAME: &str = "config";
+// every config file may have one of these extensions. Without extension, the format is detected from the content.
+const CONFIG_EXTENSIONS: [&str; 5] = ["", ".toml", ".yaml", ".yml", ".json"];
+
+// environment variables which are read directly instead of being mapped to config keys.
+// CADE_CACHE_DIR would otherwise be mapped to `cache.dir` and collide with the cache list.
+const DIRECT_ENV_VARS: [&str; 6] = ["CADE_CONFIG", "CADE_CACHE_DIR", "CADE_REDIS_URL", "CADE_HTTP_TOKEN", "CADE_HTTP_USERNAME", "CADE_HTTP_PASSWORD"];
+
+#[cfg(unix)]
+const SYSTEM_CONFIG_DIR: Option<&str> = Some("/etc/cade");
+#[cfg(not(unix))]
+const SYSTEM_CONFIG_DIR: Option<&str> = None;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_name(name: &str) -> Option<ConfigFormat> {
+        match name {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<ConfigFormat> {
+        path.extension().and_then(|extension| extension.to_str()).and_then(ConfigFormat::from_name)
+    }
+
+    /// Guesses the format of a file without a known extension.
+    /// JSON is an object, so it starts with a brace. Everything which is not valid TOML is treated as YAML.
+    fn detect(content: &str) -> ConfigFormat {
+        if content.trim_start().starts_with('{') {
+            ConfigFormat::Json
+        } else if content.parse::<toml::Table>().is_ok() {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Yaml
+        }
+    }
+
+    fn file_format(self) -> FileFormat {
+        match self {
+            ConfigFormat::Json => FileFormat::Json,
+            ConfigFormat::Toml => FileFormat::Toml,
+            ConfigFormat::Yaml => FileFormat::Yaml,
+        }
+    }
+}
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub enum CacheAccess {
     Read,
     Write,
@@ -9,32 +70,140 @@ pub enum CacheAccess {
 }
 
 
-#[derive(Deserialize)]
+/// Differences which are accepted for a cache hit, named like the corresponding ccache settings where there is one.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Sloppiness {
+    // cache sources using __DATE__, __TIME__ or __TIMESTAMP__
+    TimeMacros,
+    // ignore LANG and LC_* in the keys
+    Locale,
+    // remember header hashes also for headers modified within the last seconds
+    IncludeFileMtime,
+    // ignore include directories which exist but are empty in the keys
+    EmptyIncludeDirs,
+    // ignore the order of -D and -W options in the keys
+    FlagOrder,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct LogTypeConfig {
     pub path: String,
     #[serde(default = "bool_false_default")]
     pub append: bool
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct LogConfig {
     pub stderr: Option<LogTypeConfig>,
     pub stdout: Option<LogTypeConfig>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct FilesystemConfig {
     pub path: String,
     pub access: CacheAccess,
+    // number of directory levels named after two characters of the key each (e.g. obj/ab/cd/abcd...)
+    #[serde(default)]
+    pub shard_levels: usize,
     #[serde(default = "bool_true_default")]
     pub update_on_hit: bool,
     #[serde(default = "bool_true_default")]
     pub test_if_update_is_required: bool
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+pub struct RedisSentinelConfig {
+    pub nodes: Vec<String>,
+    pub master_name: String,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct RedisConfig {
+    // exactly one of url, sentinel or cluster_nodes has to be set
+    pub url: Option<String>,
+    pub sentinel: Option<RedisSentinelConfig>,
+    pub cluster_nodes: Option<Vec<String>>,
+    pub expire: Option<u32>,
+    // entries larger than this are split into chunks of this size. 0 disables chunking.
+    #[serde(default = "redis_chunk_size_default")]
+    pub chunk_size: usize,
+    pub access: CacheAccess,
+    #[serde(default = "bool_true_default")]
+    pub update_on_hit: bool,
+    #[serde(default = "bool_true_default")]
+    pub test_if_update_is_required: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct HttpConfig {
     pub url: String,
+    pub access: CacheAccess,
+    // connect and request timeout in seconds
+    #[serde(default = "timeout_default")]
+    pub timeout: u64,
+    #[serde(default = "bool_true_default")]
+    pub update_on_hit: bool,
+    #[serde(default = "bool_true_default")]
+    pub test_if_update_is_required: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct S3Config {
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    pub endpoint: String,
+    #[serde(default = "s3_region_default")]
+    pub region: String,
+    pub access: CacheAccess,
+    // connect and request timeout in seconds
+    #[serde(default = "timeout_default")]
+    pub timeout: u64,
+    #[serde(default = "bool_true_default")]
+    pub update_on_hit: bool,
+    #[serde(default = "bool_true_default")]
+    pub test_if_update_is_required: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SqliteConfig {
+    pub path: String,
+    pub access: CacheAccess,
+    // size limit in bytes. The least recently used entries are evicted when it is exceeded.
+    pub max_size: Option<u64>,
+    #[serde(default = "bool_true_default")]
+    pub update_on_hit: bool,
+    #[serde(default = "bool_true_default")]
+    pub test_if_update_is_required: bool,
+    // write-ahead logging allows readers during writes, but relies on shared memory, so only enable it for local disks
+    #[serde(default)]
+    pub wal: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MemcachedConfig {
+    // host:port of all servers. Keys are distributed using consistent hashing.
+    pub servers: Vec<String>,
+    // expiration in seconds, refreshed on every hit
     pub expire: Option<u32>,
+    // largest value memcached accepts (-I option of the server)
+    #[serde(default = "memcached_item_size_limit_default")]
+    pub item_size_limit: usize,
+    // larger entries are split into chunks, otherwise they are not stored at all
+    #[serde(default = "bool_true_default")]
+    pub chunk_large_entries: bool,
+    // connect and request timeout in seconds
+    #[serde(default = "timeout_default")]
+    pub timeout: u64,
+    pub access: CacheAccess,
+    #[serde(default = "bool_true_default")]
+    pub update_on_hit: bool,
+    #[serde(default = "bool_true_default")]
+    pub test_if_update_is_required: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MemoryConfig {
     pub access: CacheAccess,
     #[serde(default = "bool_true_default")]
     pub update_on_hit: bool,
@@ -42,11 +211,65 @@ pub struct RedisConfig {
     pub test_if_update_is_required: bool
 }
 
-#[derive(Deserialize)]
+// wraps another cache and injects failures, used for testing
+#[derive(Deserialize, Serialize)]
+pub struct FaultyConfig {
+    pub cache: Box<CacheConfig>,
+    #[serde(default = "bool_false_default")]
+    pub fail_reads: bool,
+    #[serde(default = "bool_false_default")]
+    pub fail_writes: bool,
+    #[serde(default = "bool_false_default")]
+    pub corrupt_reads: bool,
+    #[serde(default)]
+    pub delay_ms: u64
+}
+
+#[derive(Deserialize, Serialize)]
 #[allow(non_camel_case_types)]
 pub enum CacheConfig {
     filesystem(FilesystemConfig),
-    redis(RedisConfig)
+    redis(RedisConfig),
+    http(HttpConfig),
+    s3(S3Config),
+    sqlite(SqliteConfig),
+    memcached(MemcachedConfig),
+    memory(MemoryConfig),
+    faulty(FaultyConfig)
+}
+
+impl CacheConfig {
+    /// Whether entries of this cache may be written or moved.
+    pub fn is_writable(&self) -> bool {
+        let access = match self {
+            CacheConfig::filesystem(config) => &config.access,
+            CacheConfig::redis(config) => &config.access,
+            CacheConfig::http(config) => &config.access,
+            CacheConfig::s3(config) => &config.access,
+            CacheConfig::sqlite(config) => &config.access,
+            CacheConfig::memcached(config) => &config.access,
+            CacheConfig::memory(config) => &config.access,
+            CacheConfig::faulty(config) => return config.cache.is_writable()
+        };
+        matches!(access, CacheAccess::Write | CacheAccess::ReadWrite)
+    }
+}
+
+fn redis_chunk_size_default() -> usize {
+    16 * 1024 * 1024
+}
+
+// leaves some room for the item header within memcached's default 1 MB limit
+fn memcached_item_size_limit_default() -> usize {
+    1024 * 1024 - 1024
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+fn s3_region_default() -> String {
+    "us-east-1".to_owned()
 }
 
 fn bool_true_default() -> bool {
@@ -56,15 +279,118 @@ fn bool_false_default() -> bool {
     false
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct WrapperConfig {
     pub base_dir: Option<String>,
+    // further machine specific paths, replaced by %%%NAME%%% like base_dir is replaced by %%%BASE_DIR%%%
+    #[serde(default)]
+    pub path_map: BTreeMap<String, String>,
+    // let the compiler replace base_dir in debug information, so restored objects can be debugged on other machines
+    #[serde(default = "bool_true_default")]
+    pub debug_prefix_map: bool,
+    // no cache is used if none is configured
+    #[serde(default)]
     pub cache: Vec<CacheConfig>,
     #[serde(default = "debug_default")]
     pub debug: bool,
     #[serde(default = "panic_on_cache_content_mismatch_default")]
     pub panic_on_cache_content_mismatch: bool,
-    pub log: Option<LogConfig>
+    pub log: Option<LogConfig>,
+    // zstd level used to compress new entries
+    #[serde(default = "compression_level_default")]
+    pub compression_level: i32,
+    // settings replaced for matching compilers or source files, applied in order
+    #[serde(default)]
+    pub overrides: Vec<OverrideConfig>,
+    // only calls whose source or output path matches one of these globs are cached. All calls if empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    // calls whose source or output path matches one of these globs are not cached
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // calls with an argument matching one of these globs (e.g. "-fprofile-generate*") are not cached
+    #[serde(default)]
+    pub exclude_args: Vec<String>,
+    // environment variables affecting the output in addition to the ones known for the compiler. A trailing `*` matches a prefix.
+    #[serde(default)]
+    pub env_vars: Vec<String>,
+    // differences accepted for a cache hit
+    #[serde(default)]
+    pub sloppiness: Vec<Sloppiness>,
+    // run the executable without any cache (CADE_DISABLE)
+    #[serde(default = "bool_false_default")]
+    pub disable: bool,
+    // only read from the caches, never store anything (CADE_READONLY)
+    #[serde(default = "bool_false_default")]
+    pub readonly: bool,
+    // ignore existing entries but store the new results (CADE_RECACHE)
+    #[serde(default = "bool_false_default")]
+    pub recache: bool,
+    // files the configuration was merged from
+    #[serde(skip)]
+    pub files: Vec<PathBuf>
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct OverrideConfig {
+    // compiler family (e.g. "gcc" or "tasking"). Matches all compilers if not set.
+    pub compiler: Option<String>,
+    // glob matched against the source file. Matches all sources if not set.
+    pub source: Option<String>,
+    // settings merged on top of the base config. Lists like `cache` are replaced.
+    pub config: serde_json::Value,
+}
+
+impl OverrideConfig {
+    fn matches(&self, compiler: &str, source_file: Option<&str>) -> bool {
+        if self.compiler.as_ref().is_some_and(|name| name != compiler) {
+            return false;
+        }
+        match (&self.source, source_file) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(pattern), Some(source_file)) => {
+                let Ok(glob) = globset::Glob::new(pattern) else { return false };
+                let matcher = glob.compile_matcher();
+                let absolute = env::current_dir().map(|dir| dir.join(source_file)).unwrap_or_default();
+                matcher.is_match(source_file) || matcher.is_match(absolute)
+            }
+        }
+    }
+}
+
+/// Merges `value` into `base`. Maps are merged recursively, all other values are replaced.
+fn merge_value(base: &mut serde_json::Value, value: &serde_json::Value) {
+    match (base, value) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(value)) => {
+            for (key, value) in value {
+                match base.get_mut(key) {
+                    Some(base_value) => merge_value(base_value, value),
+                    None => { base.insert(key.to_owned(), value.clone()); }
+                }
+            }
+        },
+        (base, value) => *base = value.clone()
+    }
+}
+
+/// All problems found while loading the configuration.
+/// Every message names the file and key it refers to, as far as they are known.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub messages: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.messages.join("\n"))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn compression_level_default() -> i32 {
+    cade::common::compression::zstd::DEFAULT_LEVEL
 }
 
 fn debug_default() -> bool {
@@ -75,22 +401,553 @@ fn panic_on_cache_content_mismatch_default() -> bool {
     false
 }
 
+/// Returns the providers injected by `CADE_CACHE_DIR` and `CADE_REDIS_URL`.
+/// They are used before the configured providers, so a local directory is checked first.
+fn env_cache_configs(cache_dir: Option<String>, redis_url: Option<String>) -> Vec<CacheConfig> {
+    let mut caches = Vec::new();
+    if let Some(path) = cache_dir {
+        caches.push(CacheConfig::filesystem(FilesystemConfig {
+            path,
+            access: CacheAccess::ReadWrite,
+            shard_levels: 0,
+            update_on_hit: true,
+            test_if_update_is_required: true
+        }));
+    }
+    if let Some(url) = redis_url {
+        caches.push(CacheConfig::redis(RedisConfig {
+            url: Some(url),
+            sentinel: None,
+            cluster_nodes: None,
+            expire: None,
+            chunk_size: redis_chunk_size_default(),
+            access: CacheAccess::ReadWrite,
+            update_on_hit: true,
+            test_if_update_is_required: true
+        }));
+    }
+    caches
+}
+
+/// Returns the directory of the user config (`$XDG_CONFIG_HOME/cade`, `~/.config/cade` or `%APPDATA%\cade`).
+fn user_config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_home.join("cade"))
+}
+
+/// Returns the first existing file named `name` with one of the supported extensions in `dir`.
+fn find_config_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    CONFIG_EXTENSIONS.iter().map(|extension| dir.join(format!("{}{}", name, extension))).find(|path| path.is_file())
+}
+
+/// Returns the config files to merge, ordered from lowest to highest precedence:
+/// system config, user config, the nearest `.cade` in the current or a parent directory and the file given by `CADE_CONFIG`.
+fn find_config_files(current_dir: &Path, user_config_dir: Option<PathBuf>, explicit_config: Option<PathBuf>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Some(system_config) = SYSTEM_CONFIG_DIR.and_then(|dir| find_config_file(Path::new(dir), CONFIG_NAME)) {
+        files.push(system_config);
+    }
+    if let Some(user_config) = user_config_dir.and_then(|dir| find_config_file(&dir, CONFIG_NAME)) {
+        files.push(user_config);
+    }
+    if let Some(project_config) = current_dir.ancestors().find_map(|dir| find_config_file(dir, PROJECT_CONFIG_NAME)) {
+        files.push(project_config);
+    }
+    // an explicitly given config has to exist
+    if let Some(explicit_config) = explicit_config {
+        files.push(explicit_config);
+    }
+    files
+}
+
+/// Appends all keys of `raw` missing in `known` to `unknown`.
+/// `known` is the serialized configuration, which contains every key the schema accepts.
+fn find_unknown_keys(raw: &serde_json::Value, known: &serde_json::Value, path: &str, unknown: &mut Vec<String>) {
+    match (raw, known) {
+        (serde_json::Value::Object(raw), serde_json::Value::Object(known)) => {
+            for (key, value) in raw {
+                let key_path = if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) };
+                match known.get(key) {
+                    Some(known_value) => find_unknown_keys(value, known_value, &key_path, unknown),
+                    None => unknown.push(key_path)
+                }
+            }
+        },
+        (serde_json::Value::Array(raw), serde_json::Value::Array(known)) => {
+            for (i, (value, known_value)) in raw.iter().zip(known).enumerate() {
+                find_unknown_keys(value, known_value, &format!("{}[{}]", path, i), unknown);
+            }
+        },
+        _ => ()
+    }
+}
+
+/// Checks a single config file, so that errors can be reported together with the file name.
+fn check_file(file_config: &config::Config) -> Vec<String> {
+    let raw: serde_json::Value = match file_config.clone().try_deserialize() {
+        Ok(raw) => raw,
+        Err(err) => return vec![err.to_string()]
+    };
+    // errors within a cache entry do not tell which entry they belong to, so every entry is checked on its own
+    if let Some(caches) = raw.get("cache").and_then(|caches| caches.as_array()) {
+        let errors: Vec<String> = caches.iter().enumerate()
+            .filter_map(|(i, cache)| serde_json::from_value::<CacheConfig>(cache.clone()).err().map(|err| format!("`cache[{}]`: {}", i, err)))
+            .collect();
+        if !errors.is_empty() {
+            return errors;
+        }
+    }
+    // the same applies to unknown sloppiness values
+    if let Some(sloppiness) = raw.get("sloppiness").and_then(|sloppiness| sloppiness.as_array()) {
+        let errors: Vec<String> = sloppiness.iter().enumerate()
+            .filter_map(|(i, value)| serde_json::from_value::<Sloppiness>(value.clone()).err().map(|err| format!("`sloppiness[{}]`: {}", i, err)))
+            .collect();
+        if !errors.is_empty() {
+            return errors;
+        }
+    }
+    let parsed: WrapperConfig = match file_config.clone().try_deserialize() {
+        Ok(parsed) => parsed,
+        Err(err) => return vec![err.to_string()]
+    };
+    let mut unknown = Vec::new();
+    find_unknown_keys(&raw, &serde_json::to_value(&parsed).unwrap(), "", &mut unknown);
+    unknown.into_iter().map(|key| format!("unknown key `{}`", key)).collect()
+}
+
+fn check_url(url: &str, key: &str, errors: &mut Vec<String>) {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        errors.push(format!("`{}`: expected an http:// or https:// url, found '{}'", key, url));
+    }
+}
+
+fn check_redis_url(url: &str, key: &str, errors: &mut Vec<String>) {
+    if let Err(err) = url.into_connection_info() {
+        errors.push(format!("`{}`: invalid redis url '{}': {}", key, url, err));
+    }
+}
+
+fn check_cache_config(cache_config: &CacheConfig, key: &str, errors: &mut Vec<String>) {
+    match cache_config {
+        CacheConfig::filesystem(filesystem_config) => {
+            // the directory is created on demand, so it only has to be usable if it already exists
+            let path = Path::new(&filesystem_config.path);
+            if path.exists() && !path.is_dir() {
+                errors.push(format!("`{}.filesystem.path`: '{}' is not a directory", key, filesystem_config.path));
+            } else if path.is_dir() {
+                if let Err(err) = fs::read_dir(path) {
+                    errors.push(format!("`{}.filesystem.path`: '{}' is not readable: {}", key, filesystem_config.path, err));
+                }
+            }
+        },
+        CacheConfig::redis(redis_config) => {
+            let key = format!("{}.redis", key);
+            let configured = [redis_config.url.is_some(), redis_config.sentinel.is_some(), redis_config.cluster_nodes.is_some()];
+            if configured.iter().filter(|configured| **configured).count() != 1 {
+                errors.push(format!("`{}`: exactly one of `url`, `sentinel` or `cluster_nodes` has to be set", key));
+            }
+            if let Some(url) = &redis_config.url {
+                check_redis_url(url, &format!("{}.url", key), errors);
+            }
+            if let Some(sentinel) = &redis_config.sentinel {
+                for (i, node) in sentinel.nodes.iter().enumerate() {
+                    check_redis_url(node, &format!("{}.sentinel.nodes[{}]", key, i), errors);
+                }
+            }
+            for (i, node) in redis_config.cluster_nodes.iter().flatten().enumerate() {
+                check_redis_url(node, &format!("{}.cluster_nodes[{}]", key, i), errors);
+            }
+        },
+        CacheConfig::http(http_config) => check_url(&http_config.url, &format!("{}.http.url", key), errors),
+        CacheConfig::s3(s3_config) => check_url(&s3_config.endpoint, &format!("{}.s3.endpoint", key), errors),
+        CacheConfig::sqlite(sqlite_config) => {
+            if Path::new(&sqlite_config.path).is_dir() {
+                errors.push(format!("`{}.sqlite.path`: '{}' is a directory", key, sqlite_config.path));
+            }
+        },
+        CacheConfig::memcached(memcached_config) => {
+            if memcached_config.servers.is_empty() {
+                errors.push(format!("`{}.memcached.servers`: at least one server is required", key));
+            }
+            for (i, server) in memcached_config.servers.iter().enumerate() {
+                let valid = server.rsplit_once(':').map(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()).unwrap_or(false);
+                if !valid {
+                    errors.push(format!("`{}.memcached.servers[{}]`: expected host:port, found '{}'", key, i, server));
+                }
+            }
+        },
+        CacheConfig::memory(_) => (),
+        CacheConfig::faulty(faulty_config) => check_cache_config(&faulty_config.cache, &format!("{}.faulty.cache", key), errors),
+    }
+}
+
 impl WrapperConfig {
-    pub fn new(file_path: &String) -> Self {
-        let config = config::Config::builder()
+    pub fn new() -> Result<Self, ConfigError> {
+        let current_dir = env::current_dir().expect("Unable to determine current directory!");
+        let files = find_config_files(&current_dir, user_config_dir(), env::var_os("CADE_CONFIG").map(PathBuf::from));
+        WrapperConfig::load(&files)
+    }
+
+    fn load(files: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut errors = Vec::new();
+        let mut builder = config::Config::builder();
+        for file in files {
+            let content = match fs::read_to_string(file) {
+                Ok(content) => content,
+                Err(err) => {
+                    errors.push(format!("{}: unable to read config file: {}", file.display(), err));
+                    continue;
+                }
+            };
+            let format = ConfigFormat::from_path(file).unwrap_or_else(|| ConfigFormat::detect(&content));
+            let source = config::File::from_str(&content, format.file_format());
+            match config::Config::builder().add_source(source.clone()).build() {
+                Ok(file_config) => errors.extend(check_file(&file_config).into_iter().map(|err| format!("{}: {}", file.display(), err))),
+                Err(err) => errors.push(format!("{}: {}", file.display(), err)),
+            }
+            builder = builder.add_source(source);
+        }
+        if !errors.is_empty() {
+            return Err(ConfigError { messages: errors });
+        }
+
+        // environment variables take precedence over all files
+        let env_vars = env::vars().filter(|(name, _)| !DIRECT_ENV_VARS.contains(&name.as_str())).collect();
+        let config = builder
             .add_source(
                 config::Environment::with_prefix("CADE")
                     .try_parsing(true)
                     .separator("_")
-                    .list_separator(" "),
+                    .list_separator(" ")
+                    .source(Some(env_vars)),
             )
-            .add_source(config::File::new(&file_path, FileFormat::Json))
             .set_default("debug", false).unwrap()
             .build()
-            .unwrap();
+            .map_err(|err| ConfigError { messages: vec![err.to_string()] })?;
+
+        let mut app: WrapperConfig = config.try_deserialize().map_err(|err| ConfigError { messages: vec![err.to_string()] })?;
+        app.files = files.to_vec();
+        let mut caches = env_cache_configs(env::var("CADE_CACHE_DIR").ok(), env::var("CADE_REDIS_URL").ok());
+        caches.append(&mut app.cache);
+        app.cache = caches;
+
+        let errors = app.validate();
+        if !errors.is_empty() {
+            return Err(ConfigError { messages: errors });
+        }
+        Ok(app)
+    }
+
+    /// Checks the values which are syntactically correct but cannot work, e.g. malformed urls.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (i, cache_config) in self.cache.iter().enumerate() {
+            check_cache_config(cache_config, &format!("cache[{}]", i), &mut errors);
+        }
+        for (name, path) in &self.path_map {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                errors.push(format!("`path_map.{}`: names may only contain letters, digits and underscores", name));
+            } else if name == "BASE_DIR" && self.base_dir.is_some() {
+                errors.push("`path_map.BASE_DIR`: the name is already used by `base_dir`".to_owned());
+            } else if name == "DEP_TARGET" {
+                errors.push("`path_map.DEP_TARGET`: the name is reserved for the target of dependency files".to_owned());
+            }
+            if path.is_empty() {
+                errors.push(format!("`path_map.{}`: the path must not be empty", name));
+            }
+        }
+        if !zstd::compression_level_range().contains(&self.compression_level) {
+            errors.push(format!("`compression_level`: expected a value in {:?}, found {}", zstd::compression_level_range(), self.compression_level));
+        }
+        for (i, override_config) in self.overrides.iter().enumerate() {
+            let key = format!("overrides[{}]", i);
+            if let Some(Err(err)) = override_config.source.as_deref().map(globset::Glob::new) {
+                errors.push(format!("`{}.source`: invalid glob: {}", key, err));
+            }
+            match self.merge(&override_config.config) {
+                Ok(merged) => {
+                    let mut unknown = Vec::new();
+                    find_unknown_keys(&override_config.config, &serde_json::to_value(&merged).unwrap(), &format!("{}.config", key), &mut unknown);
+                    errors.extend(unknown.into_iter().map(|key| format!("unknown key `{}`", key)));
+                    errors.extend(merged.validate().into_iter().map(|err| format!("`{}`: {}", key, err)));
+                },
+                Err(err) => errors.push(format!("`{}.config`: {}", key, err))
+            }
+        }
+        for (key, patterns) in [("include", &self.include), ("exclude", &self.exclude), ("exclude_args", &self.exclude_args)] {
+            for (i, pattern) in patterns.iter().enumerate() {
+                if let Err(err) = globset::Glob::new(pattern) {
+                    errors.push(format!("`{}[{}]`: invalid glob: {}", key, i, err));
+                }
+            }
+        }
+        errors
+    }
+
+    fn merge(&self, value: &serde_json::Value) -> serde_json::Result<WrapperConfig> {
+        let mut merged = serde_json::to_value(self)?;
+        merge_value(&mut merged, value);
+        // overrides cannot contain further overrides
+        merged["overrides"] = serde_json::Value::Array(Vec::new());
+        let mut config: WrapperConfig = serde_json::from_value(merged)?;
+        config.files = self.files.clone();
+        Ok(config)
+    }
 
-        let app: WrapperConfig = config.try_deserialize().unwrap();
+    pub fn is_sloppy(&self, sloppiness: Sloppiness) -> bool {
+        self.sloppiness.contains(&sloppiness)
+    }
+
+    /// Returns the configuration for a call of `compiler` with `source_file`, with all matching overrides applied.
+    pub fn with_overrides(&self, compiler: &str, source_file: Option<&str>) -> Result<WrapperConfig, ConfigError> {
+        let mut config = self.merge(&serde_json::json!({})).map_err(|err| ConfigError { messages: vec![err.to_string()] })?;
+        for override_config in self.overrides.iter().filter(|override_config| override_config.matches(compiler, source_file)) {
+            if self.debug {
+                println!("Applying override for compiler {:?} and source {:?}", override_config.compiler, override_config.source);
+            }
+            config = config.merge(&override_config.config).map_err(|err| ConfigError { messages: vec![err.to_string()] })?;
+        }
+        Ok(config)
+    }
+
+    /// Serializes the effective configuration, e.g. for `cade --show-config`.
+    pub fn to_string(&self, format: ConfigFormat) -> Result<String, String> {
+        match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(|err| err.to_string()),
+            // TOML has no null, so e.g. an override unsetting `log` cannot be written
+            ConfigFormat::Toml => toml::to_string_pretty(self)
+                .map_err(|err| format!("{} (TOML cannot represent null values, use json or yaml)", err)),
+            // serde_yaml writes enums as tags, which cannot be read back. Maps with a single key can.
+            ConfigFormat::Yaml => serde_json::to_value(self)
+                .map_err(|err| err.to_string())
+                .and_then(|value| serde_yaml::to_string(&value).map_err(|err| err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_find_config_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        let sub_dir = project.join("a/b");
+        let user_dir = dir.path().join("user");
+        fs::create_dir_all(&sub_dir).unwrap();
+        fs::create_dir_all(&user_dir).unwrap();
+
+        // missing files are skipped
+        let files = find_config_files(&sub_dir, Some(user_dir.clone()), None);
+        assert!(!files.contains(&user_dir.join("config")));
+        assert!(!files.iter().any(|file| file.starts_with(dir.path())));
+
+        fs::write(project.join(".cade"), "{}").unwrap();
+        fs::write(project.join("a/.cade.toml"), "").unwrap();
+        fs::write(user_dir.join("config"), "{}").unwrap();
+        let explicit = dir.path().join("explicit.json");
+
+        let files = find_config_files(&sub_dir, Some(user_dir.clone()), Some(explicit.clone()));
+        let files: Vec<&PathBuf> = files.iter().filter(|file| file.starts_with(dir.path())).collect();
+        // only the nearest project config is used
+        assert_eq!(files, vec![&user_dir.join("config"), &project.join("a/.cade.toml"), &explicit]);
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(ConfigFormat::detect("  {\"debug\": true}"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::detect("# comment\ndebug = true\n[[cache]]\n"), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::detect("# comment\ndebug: true\ncache:\n  - memory: {}\n"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path(Path::new(".cade.yml")), Some(ConfigFormat::Yaml));
+        assert_eq!(ConfigFormat::from_path(Path::new(".cade")), None);
+    }
+
+    #[test]
+    fn test_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join(".cade.json");
+        let toml = dir.path().join("config");
+        let yaml = dir.path().join(".cade.yaml");
+        fs::write(&json, r#"{"base_dir": "/base", "cache": [{"filesystem": {"path": "/cache", "access": "ReadWrite", "shard_levels": 2}}]}"#).unwrap();
+        fs::write(&toml, "base_dir = \"/base\"\n[[cache]]\n[cache.filesystem]\npath = \"/cache\"\naccess = \"ReadWrite\"\nshard_levels = 2\n").unwrap();
+        fs::write(&yaml, "base_dir: /base\ncache:\n  - filesystem:\n      path: /cache\n      access: ReadWrite\n      shard_levels: 2\n").unwrap();
+
+        let show = |path: &Path, format: ConfigFormat| WrapperConfig::load(&[path.to_owned()]).unwrap().to_string(format).unwrap();
+        let expected = show(&json, ConfigFormat::Json);
+        assert_eq!(show(&toml, ConfigFormat::Json), expected);
+        assert_eq!(show(&yaml, ConfigFormat::Json), expected);
+
+        // the output of --show-config can be loaded again
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let output = dir.path().join("output");
+            fs::write(&output, show(&json, format)).unwrap();
+            assert_eq!(show(&output, ConfigFormat::Json), expected);
+        }
+    }
+
+    #[test]
+    fn test_show_null() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".cade.json");
+        fs::write(&path, r#"{"overrides": [{"compiler": "gcc", "config": {"log": null}}]}"#).unwrap();
+        let config = WrapperConfig::load(&[path]).unwrap();
+
+        assert!(config.to_string(ConfigFormat::Json).unwrap().contains("null"));
+        assert!(config.to_string(ConfigFormat::Yaml).is_ok());
+        assert!(config.to_string(ConfigFormat::Toml).unwrap_err().contains("null"));
+    }
 
-        app
+    #[test]
+    fn test_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let low = dir.path().join("low.toml");
+        let high = dir.path().join("high.yaml");
+        fs::write(&low, "base_dir = \"/low\"\ndebug = true\n[[cache]]\nmemory = { access = \"Read\" }\n[[cache]]\nmemory = { access = \"Read\" }\n").unwrap();
+        fs::write(&high, "base_dir: /high\ncache:\n  - memory:\n      access: ReadWrite\n").unwrap();
+
+        let config = WrapperConfig::load(&[low, high]).unwrap();
+        assert_eq!(config.base_dir.as_deref(), Some("/high"));
+        assert!(config.debug);
+        // lists are replaced, not merged
+        assert_eq!(config.cache.len(), 1);
+    }
+
+    fn load_errors(content: &str) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".cade.toml");
+        fs::write(&path, content).unwrap();
+        let errors = WrapperConfig::load(std::slice::from_ref(&path)).err().map(|err| err.messages).unwrap_or_default();
+        // every message names the file
+        assert!(errors.iter().all(|err| err.starts_with(path.to_str().unwrap())));
+        errors
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(load_errors("debug = ").len(), 1);
+        assert!(load_errors("debug = \"maybe\"")[0].contains("debug"));
+        assert!(load_errors("[[cache]]\n[cache.memory]\naccess = \"Read\"\n[[cache]]\n[cache.filesystem]\npath = \"/cache\"")[0].ends_with("`cache[1]`: missing field `access`"));
+        assert!(load_errors("[[cache]]\n[cache.ftp]\npath = \"/cache\"")[0].contains("`cache[0]`: unknown variant `ftp`"));
+        assert!(load_errors("sloppiness = [\"locale\", \"locales\"]")[0].ends_with("`sloppiness[1]`: unknown variant `locales`, expected one of `time_macros`, `locale`, `include_file_mtime`, `empty_include_dirs`, `flag_order`"));
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let errors = load_errors("debg = true\n[[cache]]\n[cache.filesystem]\npath = \"/cache\"\naccess = \"Read\"\nshard_level = 2\n");
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].ends_with("unknown key `debg`") || errors[1].ends_with("unknown key `debg`"));
+        assert!(errors.iter().any(|err| err.ends_with("unknown key `cache[0].filesystem.shard_level`")));
+    }
+
+    #[test]
+    fn test_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let config = WrapperConfig {
+            base_dir: None,
+            path_map: BTreeMap::from([("SD K".to_owned(), "/sdk".to_owned())]),
+            debug_prefix_map: true,
+            cache: vec![
+                CacheConfig::filesystem(FilesystemConfig { path: file.to_str().unwrap().to_owned(), access: CacheAccess::ReadWrite, shard_levels: 0, update_on_hit: true, test_if_update_is_required: true }),
+                CacheConfig::redis(RedisConfig { url: Some("localhost:6379".to_owned()), sentinel: None, cluster_nodes: Some(Vec::new()), expire: None, chunk_size: 0, access: CacheAccess::ReadWrite, update_on_hit: true, test_if_update_is_required: true }),
+                CacheConfig::http(HttpConfig { url: "cache.example.com".to_owned(), access: CacheAccess::ReadWrite, timeout: 1, update_on_hit: true, test_if_update_is_required: true }),
+            ],
+            debug: false,
+            panic_on_cache_content_mismatch: false,
+            log: None,
+            compression_level: 3,
+            overrides: Vec::new(),
+            include: Vec::new(),
+            exclude: vec!["src/[a-".to_owned()],
+            exclude_args: Vec::new(),
+            env_vars: Vec::new(),
+            sloppiness: Vec::new(),
+            disable: false,
+            readonly: false,
+            recache: false,
+            files: Vec::new()
+        };
+
+        let errors = config.validate();
+        assert_eq!(errors.len(), 6);
+        assert!(errors[0].starts_with("`cache[0].filesystem.path`"));
+        assert!(errors[1].starts_with("`cache[1].redis`: exactly one"));
+        assert!(errors[2].starts_with("`cache[1].redis.url`: invalid redis url"));
+        assert!(errors[3].starts_with("`cache[2].http.url`"));
+        assert!(errors[4].starts_with("`path_map.SD K`"));
+        assert!(errors[5].starts_with("`exclude[0]`: invalid glob"));
+    }
+
+    #[test]
+    fn test_env_cache_configs() {
+        assert!(env_cache_configs(None, None).is_empty());
+        let caches = env_cache_configs(Some("/cache".to_owned()), Some("redis://localhost".to_owned()));
+        assert!(matches!(&caches[0], CacheConfig::filesystem(config) if config.path == "/cache"));
+        assert!(matches!(&caches[1], CacheConfig::redis(config) if config.url.as_deref() == Some("redis://localhost")));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".cade.toml");
+        fs::write(&path, r#"
+            base_dir = "/base"
+            [[cache]]
+            memory = { access = "ReadWrite" }
+            [[cache]]
+            memory = { access = "ReadWrite" }
+
+            [[overrides]]
+            compiler = "tasking"
+            config = { base_dir = "/tasking" }
+
+            [[overrides]]
+            source = "**/vendor/**"
+            [overrides.config]
+            compression_level = 19
+            cache = [{ memory = { access = "Read", update_on_hit = false } }]
+        "#).unwrap();
+        let config = WrapperConfig::load(&[path]).unwrap();
+
+        let gcc = config.with_overrides("gcc", Some("src/main.c")).unwrap();
+        assert_eq!(gcc.base_dir.as_deref(), Some("/base"));
+        assert_eq!(gcc.compression_level, 3);
+        assert_eq!(gcc.cache.len(), 2);
+
+        let tasking = config.with_overrides("tasking", Some("vendor/lib/lib.c")).unwrap();
+        assert_eq!(tasking.base_dir.as_deref(), Some("/tasking"));
+        assert_eq!(tasking.compression_level, 19);
+        assert!(matches!(&tasking.cache[..], [CacheConfig::memory(memory)] if !memory.update_on_hit));
+
+        // source globs never match calls without source file
+        assert_eq!(config.with_overrides("gcc", None).unwrap().compression_level, 3);
+    }
+
+    #[test]
+    fn test_override_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".cade.toml");
+        fs::write(&path, r#"
+            [[overrides]]
+            source = "src/[a-"
+            config = { compresion_level = 19 }
+            [[overrides]]
+            config = { compression_level = 100 }
+            [[overrides]]
+            config = { debug = "maybe" }
+        "#).unwrap();
+        // the overrides are checked on the merged config, so the errors do not name a file
+        let errors = WrapperConfig::load(&[path]).err().unwrap().messages;
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].contains("`overrides[0].source`: invalid glob"));
+        assert!(errors[1].contains("unknown key `overrides[0].config.compresion_level`"));
+        assert!(errors[2].contains("`overrides[1]`: `compression_level`"));
+        assert!(errors[3].contains("`overrides[2].config`: invalid type"));
+    }
+}
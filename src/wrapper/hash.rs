@@ -1,8 +1,19 @@
-#[allow(dead_code)]
+use std::{io, path::Path};
+
 pub fn hash(input: &[u8]) -> String {
     blake3::hash(input).to_string()
 }
 
+/// Hashes the content of a file. Large files are memory mapped and hashed on several threads.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_mmap_rayon(path)?;
+    Ok(hasher.finalize().to_string())
+}
+
+// layout of the cache keys. Increase it whenever the fields hashed into a key change, so entries of the old layout are never matched.
+pub const KEY_SCHEMA_VERSION: u32 = 2;
+
 pub struct Hasher {
     hasher: blake3::Hasher
 }
@@ -14,11 +25,44 @@ impl Hasher {
         }
     }
 
-    pub fn update(&mut self, input: &[u8]) {
-        self.hasher.update(input);
+    /// Hasher for a cache key of the given kind (e.g. "obj").
+    /// The key schema and cade version are hashed first, so keys of other versions never collide with this one.
+    pub fn new_key(kind: &str) -> Hasher {
+        let mut hasher = Hasher::new();
+        hasher.update_field(&KEY_SCHEMA_VERSION.to_le_bytes());
+        hasher.update_field(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update_field(kind.as_bytes());
+        hasher
+    }
+
+    /// Adds `field` prefixed by its length, so the boundaries between fields are part of the hash.
+    pub fn update_field(&mut self, field: &[u8]) {
+        self.hasher.update(&(field.len() as u64).to_le_bytes());
+        self.hasher.update(field);
     }
 
     pub fn finalize(self) -> String {
         self.hasher.finalize().to_string()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(kind: &str, fields: &[&str]) -> String {
+        let mut hasher = Hasher::new_key(kind);
+        for field in fields {
+            hasher.update_field(field.as_bytes());
+        }
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_fields() {
+        assert_eq!(key("obj", &["ab", "c"]), key("obj", &["ab", "c"]));
+        assert_ne!(key("obj", &["ab", "c"]), key("obj", &["a", "bc"]));
+        assert_ne!(key("obj", &["ab", ""]), key("obj", &["ab"]));
+        assert_ne!(key("obj", &["a"]), key("dep", &["a"]));
+    }
+}
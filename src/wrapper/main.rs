@@ -2,7 +2,7 @@ use std::{env, ffi::OsStr, fs::OpenOptions, io::{self, Write}, path::Path, proce
 
 use config::LogConfig;
 
-use crate::{cache_handler::CacheHandler, cache::cache::Cache};
+use crate::{cache_handler::CacheHandler, cache::cache::Cache, path_map::PathMap};
 
 mod config;
 mod cache_handler;
@@ -10,6 +10,7 @@ mod nocache_handler;
 mod compiler;
 mod hash;
 mod cache;
+mod path_map;
 
 fn write(data: &[u8], is_stdout: bool, log_config: &Option<LogConfig>, cache_handler: &dyn CacheHandler) {
     if is_stdout {
@@ -39,20 +40,71 @@ fn main() {
 
     let exe_option = args.get(0);
 
-    let config = config::WrapperConfig::new(&".cade".to_owned());
-    let cache = Cache::new(&config);
+    let config = match config::WrapperConfig::new() {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Invalid cade configuration:\n{}", err);
+            std::process::exit(1);
+        }
+    };
 
     match exe_option {
+        Some(option) if option == "--check-config" => {
+            // invalid configurations already exited above
+            println!("Configuration is valid. Loaded from:");
+            for file in &config.files {
+                println!("  {}", file.display());
+            }
+        }
+        Some(option) if option == "--show-config" => {
+            // print the merged configuration of all config files and environment variables
+            let format_name = args.get(1).map(String::as_str).unwrap_or("json");
+            match config::ConfigFormat::from_name(format_name) {
+                Some(format) => match config.to_string(format) {
+                    Ok(output) => println!("{}", output.trim_end()),
+                    Err(err) => {
+                        println!("Unable to show the configuration as {}: {}", format_name, err);
+                        std::process::exit(1);
+                    }
+                },
+                None => {
+                    println!("Unknown config format '{}'. Use json, toml or yaml.", format_name);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(option) if option == "--migrate-cache" => {
+            // move entries of existing caches to the configured layout (e.g. after enabling sharding)
+            if let Some(cache) = Cache::new(&config).as_ref() {
+                if let Err(err) = cache.migrate() {
+                    println!("Unable to migrate cache: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
         Some(exe_path) => {
-            let mut cache_handler: Box<dyn CacheHandler>;
             let exe =  Path::new(exe_path).file_stem().and_then(OsStr::to_str).expect("could not determine executable");
+            let is_compiler = matches!(exe, "gcc" | "g++" | "tricore-gcc" | "tricore-g++" | "cctc");
+
+            // overrides depend on compiler and source file, so they have to be applied before the cache is set up
+            let config = if is_compiler {
+                let (compiler_name, source_file) = compiler::compile_handler::Compiler::probe(exe, &args, &config);
+                match config.with_overrides(&compiler_name, source_file.as_deref()) {
+                    Ok(config) => config,
+                    Err(err) => {
+                        println!("Invalid cade configuration:\n{}", err);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                config
+            };
+
+            let cache = if config.disable { None } else { Cache::new(&config) };
+            let path_map = PathMap::new(&config);
+            let mut cache_handler: Box<dyn CacheHandler>;
             match exe {
-                "gcc" |
-                "g++" |
-                "tricore-gcc" |
-                "tricore-g++" |
-                "cctc"
-                => {
+                _ if is_compiler => {
                     cache_handler = Box::new(compiler::compile_handler::Compiler::new(exe, cache.as_ref(), &config));
                 }
                 _ => {
@@ -85,15 +137,11 @@ fn main() {
 
                     if let Some(key) = key {
                         if let Ok((data, _)) = cache.as_ref().unwrap().get_entry(Some(category), key, Some(&provider_id)) {
-                            match config.base_dir.as_ref() {
-                                Some(base_dir) => {
-                                    let mut output = String::from_utf8(data).unwrap();
-                                    output = output.replace("%%%BASE_DIR%%%", base_dir);
-                                    write(&output.as_bytes(), i == 0, &config.log, cache_handler.as_ref());
-                                }
-                                None => {
-                                    write(&data, i == 0, &config.log, cache_handler.as_ref());
-                                }
+                            if path_map.is_empty() {
+                                write(&data, i == 0, &config.log, cache_handler.as_ref());
+                            } else {
+                                let output = path_map.resolve_placeholders(&String::from_utf8(data).unwrap());
+                                write(&output.as_bytes(), i == 0, &config.log, cache_handler.as_ref());
                             }
                         }
                     }
@@ -116,11 +164,8 @@ fn main() {
 
                             cache_handler.cache_push();
 
-                            if config.base_dir.is_some() {
-                                let base_dir = config.base_dir.as_ref().unwrap();
-                                stdout = stdout.replace(base_dir, "%%%BASE_DIR%%%");
-                                stderr = stderr.replace(base_dir, "%%%BASE_DIR%%%");
-                            }
+                            stdout = path_map.insert_placeholders(&stdout);
+                            stderr = path_map.insert_placeholders(&stderr);
 
                             // do not cache this call. It may have been recalculated.
                             match cache_handler.get_stdout_key() {
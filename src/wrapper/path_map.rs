@@ -0,0 +1,231 @@
+use std::fs;
+
+use crate::config::WrapperConfig;
+
+// name of the mapping created from the `base_dir` setting
+pub const BASE_DIR_NAME: &str = "BASE_DIR";
+
+/// Replaces machine specific path prefixes with named placeholders (`%%%NAME%%%`) and back.
+/// This makes cache entries independent of the location of the workspace, SDKs or toolchains.
+///
+/// Paths are only replaced on component boundaries, `/` and `\` are treated as the same separator
+/// and on Windows the comparison ignores case.
+pub struct PathMap {
+    // (placeholder, path) used to restore the paths
+    mappings: Vec<(String, String)>,
+    // (path, placeholder) of all spellings of the mapped paths, longest path first
+    patterns: Vec<(String, String)>,
+    ignore_case: bool,
+}
+
+pub fn placeholder(name: &str) -> String {
+    format!("%%%{}%%%", name)
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+// characters which continue a file name, so a match ending before them is only part of a name
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || "_-.~+@".contains(c)
+}
+
+/// Removes `.` components, duplicate and trailing separators from `path` without touching the file system.
+/// `..` components are kept, because resolving them lexically is wrong if the parent is a symlink.
+/// A backslash followed by a space is an escaped space (as in make dependency files) and not a separator.
+pub fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let mut separators: Vec<char> = Vec::new();
+    let mut start = 0;
+    let mut chars = path.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let escaped_space = c == '\\' && chars.peek().map(|(_, next)| *next == ' ').unwrap_or(false);
+        if is_separator(c) && !escaped_space {
+            components.push(&path[start..i]);
+            separators.push(c);
+            start = i + 1;
+        }
+    }
+    components.push(&path[start..]);
+
+    let mut result = String::new();
+    for (i, component) in components.iter().enumerate() {
+        let is_root = i == 0 && component.is_empty();
+        if (component.is_empty() || *component == ".") && !is_root && !(i == 0 && components.len() == 1) {
+            continue;
+        }
+        if !result.is_empty() && !result.ends_with(is_separator) {
+            result.push(separators[i - 1]);
+        }
+        result.push_str(component);
+        if is_root {
+            result.push(separators.first().copied().unwrap_or('/'));
+        }
+    }
+    if result.is_empty() && !path.is_empty() {
+        result.push('.');
+    }
+    result
+}
+
+impl PathMap {
+    pub fn new(config: &WrapperConfig) -> PathMap {
+        let base_dir = config.base_dir.iter().map(|path| (BASE_DIR_NAME, path));
+        PathMap::from_mappings(base_dir.chain(config.path_map.iter().map(|(name, path)| (name.as_str(), path))), cfg!(windows))
+    }
+
+    pub fn from_mappings<'a>(mappings: impl Iterator<Item = (&'a str, &'a String)>, ignore_case: bool) -> PathMap {
+        let mappings: Vec<(String, String)> = mappings
+            .filter(|(_, path)| !path.is_empty())
+            .map(|(name, path)| (placeholder(name), normalize(path)))
+            .collect();
+
+        let mut patterns = Vec::new();
+        for (placeholder, path) in &mappings {
+            patterns.push((path.clone(), placeholder.clone()));
+            // a checkout reached via symlink shows up with its real path in the output of the compiler
+            if let Ok(canonical) = fs::canonicalize(path) {
+                let canonical = canonical.to_string_lossy();
+                let canonical = normalize(canonical.strip_prefix(r"\\?\").unwrap_or(&canonical));
+                if canonical != *path {
+                    patterns.push((canonical, placeholder.clone()));
+                }
+            }
+        }
+        // a mapping nested in another one has to be replaced first
+        patterns.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+        PathMap { mappings, patterns, ignore_case }
+    }
+
+    /// All spellings of the mapping `name`, i.e. the configured path and its real path if it differs.
+    pub fn get_paths(&self, name: &str) -> Vec<&str> {
+        let placeholder = placeholder(name);
+        let mut paths: Vec<&str> = self.patterns.iter().filter(|(_, p)| *p == placeholder).map(|(path, _)| path.as_str()).collect();
+        // shorter paths first, so the configured path usually comes first
+        paths.reverse();
+        paths
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    // unifies separators and case, keeping the byte offsets of the text
+    fn fold(&self, text: &str) -> String {
+        let text = text.replace('\\', "/");
+        if self.ignore_case { text.to_ascii_lowercase() } else { text }
+    }
+
+    /// Replaces all occurrences of `path` in `text` which start and end on a path component boundary.
+    fn replace_path(&self, text: &str, path: &str, placeholder: &str) -> String {
+        let folded_text = self.fold(text);
+        let folded_path = self.fold(path);
+        let mut result = String::new();
+        let mut last = 0;
+        for (start, _) in folded_text.match_indices(&folded_path) {
+            let end = start + folded_path.len();
+            // the path may follow an option (`-I/ws`), but not a directory (`/a/ws`, `a/ws`)
+            let prefix = folded_text[..start].trim_end_matches(is_name_char);
+            let name = &folded_text[prefix.len()..start];
+            let starts_component = !prefix.ends_with('/') && (name.is_empty() || name.starts_with('-'));
+            let ends_component = folded_path.ends_with('/') || !folded_text[end..].starts_with(is_name_char);
+            if start >= last && starts_component && ends_component {
+                result.push_str(&text[last..start]);
+                result.push_str(placeholder);
+                last = end;
+            }
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+
+    /// Replaces all mapped paths in `text` with their placeholders.
+    pub fn insert_placeholders(&self, text: &str) -> String {
+        self.patterns.iter().fold(text.to_owned(), |text, (path, placeholder)| self.replace_path(&text, path, placeholder))
+    }
+
+    /// Replaces all placeholders in `text` with the paths of this machine.
+    pub fn resolve_placeholders(&self, text: &str) -> String {
+        self.mappings.iter().fold(text.to_owned(), |text, (placeholder, path)| text.replace(placeholder, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_map(mappings: &[(&str, &str)], ignore_case: bool) -> PathMap {
+        let mappings: Vec<(&str, String)> = mappings.iter().map(|(name, path)| (*name, path.to_string())).collect();
+        PathMap::from_mappings(mappings.iter().map(|(name, path)| (*name, path)), ignore_case)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let path_map = path_map(&[("WORKSPACE", "/home/ci/ws"), ("SDK", "/opt/sdk-3.2")], false);
+        let text = "-I/home/ci/ws/include -I/opt/sdk-3.2/include /home/ci/ws/main.c";
+        let mapped = path_map.insert_placeholders(text);
+        assert_eq!(mapped, "-I%%%WORKSPACE%%%/include -I%%%SDK%%%/include %%%WORKSPACE%%%/main.c");
+
+        let other_machine = self::path_map(&[("WORKSPACE", "/builds/42"), ("SDK", "C:/sdk")], false);
+        assert_eq!(other_machine.resolve_placeholders(&mapped), "-I/builds/42/include -IC:/sdk/include /builds/42/main.c");
+    }
+
+    #[test]
+    fn test_nested_mappings() {
+        // the SDK is checked out inside the workspace
+        let path_map = path_map(&[("BASE_DIR", "/ws"), ("SDK", "/ws/sdk")], false);
+        assert_eq!(path_map.insert_placeholders("/ws/sdk/a.h /ws/b.h"), "%%%SDK%%%/a.h %%%BASE_DIR%%%/b.h");
+        assert_eq!(path_map.resolve_placeholders("%%%SDK%%%/a.h %%%BASE_DIR%%%/b.h"), "/ws/sdk/a.h /ws/b.h");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/ws/./src/"), "/ws/src");
+        assert_eq!(normalize("/ws//src/."), "/ws/src");
+        assert_eq!(normalize("C:\\ws\\.\\src\\"), "C:\\ws\\src");
+        assert_eq!(normalize("../include/./a.h"), "../include/a.h");
+        assert_eq!(normalize("/ws/a\\ b.h"), "/ws/a\\ b.h");
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("./"), ".");
+        assert_eq!(normalize("a.h"), "a.h");
+    }
+
+    #[test]
+    fn test_component_boundaries() {
+        let path_map = path_map(&[("BASE_DIR", "/ws/")], false);
+        assert_eq!(path_map.insert_placeholders("/ws/a.c /ws2/a.c /a/ws/a.c /ws.old/a.c /ws"), "%%%BASE_DIR%%%/a.c /ws2/a.c /a/ws/a.c /ws.old/a.c %%%BASE_DIR%%%");
+        assert_eq!(path_map.insert_placeholders("a/ws/a.c -I/ws/inc --sysroot=/ws"), "a/ws/a.c -I%%%BASE_DIR%%%/inc --sysroot=%%%BASE_DIR%%%");
+        assert_eq!(path_map.insert_placeholders("/ws/a.c:1:2: warning"), "%%%BASE_DIR%%%/a.c:1:2: warning");
+        assert_eq!(path_map.insert_placeholders("\"/ws\" -I/ws"), "\"%%%BASE_DIR%%%\" -I%%%BASE_DIR%%%");
+    }
+
+    #[test]
+    fn test_separators_and_case() {
+        let path_map = path_map(&[("BASE_DIR", "C:/Work/ws")], false);
+        assert_eq!(path_map.insert_placeholders("C:\\Work\\ws\\src\\a.c"), "%%%BASE_DIR%%%\\src\\a.c");
+        assert_eq!(path_map.insert_placeholders("-IC:\\Work\\ws\\inc"), "-I%%%BASE_DIR%%%\\inc");
+        assert_eq!(path_map.insert_placeholders("c:\\work\\WS\\src\\a.c"), "c:\\work\\WS\\src\\a.c");
+
+        let path_map = self::path_map(&[("BASE_DIR", "C:/Work/ws")], true);
+        assert_eq!(path_map.insert_placeholders("c:\\work\\WS\\src\\a.c"), "%%%BASE_DIR%%%\\src\\a.c");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        let link = dir.path().join("link");
+        fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let path_map = path_map(&[("BASE_DIR", link.to_str().unwrap())], false);
+        let real = fs::canonicalize(&real).unwrap();
+        let text = format!("{}/a.c {}/b.c", real.display(), link.display());
+        assert_eq!(path_map.insert_placeholders(&text), "%%%BASE_DIR%%%/a.c %%%BASE_DIR%%%/b.c");
+        // placeholders are resolved to the configured path
+        assert_eq!(path_map.resolve_placeholders("%%%BASE_DIR%%%/a.c"), format!("{}/a.c", link.display()));
+    }
+}
@@ -0,0 +1,591 @@
+//! End-to-end tests running the `cade` binary against a fake compiler.
+#![cfg(unix)]
+
+use std::{fs, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, process::{Command, Output}};
+
+use tempfile::TempDir;
+
+// Stand-in for gcc. It copies the source and all included headers (two levels deep) into the object file,
+// writes a make dependency file listing them and logs every invocation next to itself.
+const FAKE_GCC: &str = r#"#!/bin/sh
+echo "$@" >> "$(dirname "$0")/invocations.log"
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -c) src="$2"; shift;;
+        -o) out="$2"; shift;;
+        -MF) dep="$2"; shift;;
+        --dep-file=*) dep="${1#--dep-file=}";;
+    esac
+    shift
+done
+dir=$(dirname "$src")
+headers=$(sed -n 's/^#include "\(.*\)"$/\1/p' "$src")
+files="$src"
+for header in $headers; do
+    files="$files $dir/$header"
+    for nested in $(sed -n 's/^#include "\(.*\)"$/\1/p' "$dir/$header"); do
+        files="$files $dir/$nested"
+    done
+done
+cat $files > "$out"
+printf '%s:' "$out" > "$dep"
+for file in $files; do
+    printf ' \\\n %s' "$file" >> "$dep"
+done
+echo "compiling $src"
+echo "$src:1: warning: fake warning" >&2
+"#;
+
+struct Toolchain {
+    dir: TempDir,
+    cache: TempDir,
+}
+
+impl Toolchain {
+    fn new() -> Toolchain {
+        let dir = tempfile::tempdir().unwrap();
+        let gcc = dir.path().join("gcc");
+        fs::write(&gcc, FAKE_GCC).unwrap();
+        fs::set_permissions(&gcc, fs::Permissions::from_mode(0o755)).unwrap();
+        Toolchain { dir, cache: tempfile::tempdir().unwrap() }
+    }
+
+    fn gcc(&self) -> PathBuf {
+        self.dir.path().join("gcc")
+    }
+
+    fn invocations(&self) -> usize {
+        self.invocation_log().lines().count()
+    }
+
+    fn invocation_log(&self) -> String {
+        fs::read_to_string(self.dir.path().join("invocations.log")).unwrap_or_default()
+    }
+
+    /// Creates a project with a source file including a header and a `.cade` using the shared cache.
+    /// With `base_dir` set, the project directory is configured as base dir.
+    fn project(&self, base_dir: bool, extra_config: &str) -> Project {
+        self.project_with_cache(self.cache.path(), base_dir, extra_config)
+    }
+
+    fn project_with_cache(&self, cache: &Path, base_dir: bool, extra_config: &str) -> Project {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::create_dir(dir.path().join("build")).unwrap();
+        fs::write(dir.path().join("src/main.c"), "#include \"main.h\"\nint main() { return VALUE; }\n").unwrap();
+        fs::write(dir.path().join("src/main.h"), "#define VALUE 0\n").unwrap();
+        let base_dir = if base_dir { format!(r#", "base_dir": "{}""#, dir.path().display()) } else { String::new() };
+        let config = format!(r#"{{
+            "cache": [{{ "filesystem": {{ "path": "{}", "access": "ReadWrite" }} }}]{}{}
+        }}"#, cache.display(), base_dir, extra_config);
+        fs::write(dir.path().join(".cade"), config).unwrap();
+        Project { dir }
+    }
+}
+
+struct Project {
+    dir: TempDir,
+}
+
+impl Project {
+    fn path(&self, relative: &str) -> PathBuf {
+        self.dir.path().join(relative)
+    }
+
+    /// Command compiling the source of the project.
+    /// The user config directory is redirected to the toolchain, so configs of the machine running the tests are ignored.
+    fn command(&self, toolchain: &Toolchain) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cade"));
+        command
+            .current_dir(self.dir.path())
+            .env("XDG_CONFIG_HOME", toolchain.dir.path())
+            .env_remove("CADE_CONFIG")
+            .arg(toolchain.gcc())
+            .arg("-c").arg(self.path("src/main.c"))
+            .arg("-o").arg(self.path("build/main.o"))
+            .arg("-MF").arg(self.path("build/main.d"));
+        command
+    }
+
+    fn compile(&self, toolchain: &Toolchain) -> Output {
+        run(&mut self.command(toolchain))
+    }
+}
+
+fn run(command: &mut Command) -> Output {
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "cade failed: {}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn test_miss_then_hit() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+    let object = fs::read(project.path("build/main.o")).unwrap();
+
+    fs::remove_file(project.path("build/main.o")).unwrap();
+    fs::remove_file(project.path("build/main.d")).unwrap();
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+    assert_eq!(fs::read(project.path("build/main.o")).unwrap(), object);
+    assert!(read(&project.path("build/main.d")).contains("main.h"));
+}
+
+#[test]
+fn test_include_change() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, r#", "panic_on_cache_content_mismatch": true"#);
+    fs::write(project.path("src/a.h"), "#define VALUE 1\n").unwrap();
+    fs::write(project.path("src/b.h"), "#define VALUE 2\n").unwrap();
+
+    // adding a dependency set rewrites the manifest of the source, which is no content mismatch
+    fs::write(project.path("src/main.h"), "#include \"a.h\"\n").unwrap();
+    project.compile(&toolchain);
+    fs::write(project.path("src/main.h"), "#include \"b.h\"\n").unwrap();
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+
+    // both dependency sets are kept
+    fs::write(project.path("src/main.h"), "#include \"a.h\"\n").unwrap();
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(read(&project.path("build/main.o")).contains("VALUE 1"));
+}
+
+#[test]
+fn test_header_change() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+
+    project.compile(&toolchain);
+    fs::write(project.path("src/main.h"), "#define VALUE 1\n").unwrap();
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(read(&project.path("build/main.o")).contains("VALUE 1"));
+
+    // both variants are cached now
+    fs::write(project.path("src/main.h"), "#define VALUE 0\n").unwrap();
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(read(&project.path("build/main.o")).contains("VALUE 0"));
+}
+
+#[test]
+fn test_output_replay() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+
+    project.compile(&toolchain);
+    let hit = project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+
+    let source = project.path("src/main.c");
+    assert_eq!(String::from_utf8(hit.stdout).unwrap(), format!("compiling {}\n", source.display()));
+    assert_eq!(String::from_utf8(hit.stderr).unwrap(), format!("{}:1: warning: fake warning\n", source.display()));
+}
+
+#[test]
+fn test_base_dir_relocation() {
+    let toolchain = Toolchain::new();
+    let first = toolchain.project(true, "");
+    let second = toolchain.project(true, "");
+
+    first.compile(&toolchain);
+    let hit = second.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+
+    // paths in the dependency file and the replayed output point to the second project
+    let dep = read(&second.path("build/main.d"));
+    assert!(dep.contains(second.path("src/main.h").to_str().unwrap()));
+    assert!(!dep.contains(first.dir.path().to_str().unwrap()));
+    let stdout = String::from_utf8(hit.stdout).unwrap();
+    assert_eq!(stdout, format!("compiling {}\n", second.path("src/main.c").display()));
+}
+
+#[test]
+fn test_log_templating() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, r#", "log": {
+        "stdout": { "path": "{obj_folder}/stdout.log" },
+        "stderr": { "path": "{obj_path}.stderr.log", "append": true }
+    }"#);
+
+    project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+
+    let source = project.path("src/main.c");
+    assert_eq!(read(&project.path("build/stdout.log")), format!("compiling {}\n", source.display()));
+    // the warning is logged on the miss and again when it is replayed on the hit
+    assert_eq!(read(&project.path("build/main.o.stderr.log")), format!("{}:1: warning: fake warning\n", source.display()).repeat(2));
+}
+
+#[test]
+fn test_config_in_parent_directory() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+
+    run(project.command(&toolchain).current_dir(project.path("build")));
+    run(project.command(&toolchain).current_dir(project.path("src")));
+    assert_eq!(toolchain.invocations(), 1);
+}
+
+#[test]
+fn test_explicit_and_user_config() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    let config = toolchain.dir.path().join("config.json");
+    fs::rename(project.path(".cade"), &config).unwrap();
+
+    run(project.command(&toolchain).env("CADE_CONFIG", &config));
+    run(project.command(&toolchain).env("CADE_CONFIG", &config));
+    assert_eq!(toolchain.invocations(), 1);
+
+    fs::create_dir(toolchain.dir.path().join("cade")).unwrap();
+    fs::rename(&config, toolchain.dir.path().join("cade/config")).unwrap();
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+}
+
+#[test]
+fn test_without_config() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    fs::remove_file(project.path(".cade")).unwrap();
+
+    // without any config the compiler is executed every time
+    project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(project.path("build/main.o").is_file());
+}
+
+#[test]
+fn test_show_config() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    fs::rename(project.path(".cade"), project.path(".cade.json")).unwrap();
+    fs::write(project.path("src/.cade.yaml"), "debug: true\n").unwrap();
+
+    let output = run(Command::new(env!("CARGO_BIN_EXE_cade"))
+        .current_dir(project.path("src"))
+        .env("XDG_CONFIG_HOME", toolchain.dir.path())
+        .args(["--show-config", "toml"]));
+    // only the nearest project config is used
+    let config = String::from_utf8(output.stdout).unwrap();
+    assert!(config.contains("debug = true"));
+    assert!(!config.contains("[cache.filesystem]"));
+}
+
+#[test]
+fn test_check_config() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    let check = || Command::new(env!("CARGO_BIN_EXE_cade"))
+        .current_dir(project.path("src"))
+        .env("XDG_CONFIG_HOME", toolchain.dir.path())
+        .arg("--check-config")
+        .output()
+        .unwrap();
+
+    assert!(check().status.success());
+
+    fs::write(project.path(".cade"), r#"{"cache": [{"redis": {"url": "localhost", "access": "ReadWrite"}}], "bas_dir": "/"}"#).unwrap();
+    let output = check();
+    assert_eq!(output.status.code(), Some(1));
+    let message = String::from_utf8(output.stdout).unwrap();
+    assert!(message.contains(&format!("{}: unknown key `bas_dir`", project.path(".cade").display())));
+
+    // a broken config does not fall back to running without cache
+    let output = project.command(&toolchain).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(toolchain.invocations(), 0);
+}
+
+#[test]
+fn test_env_controls() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+
+    run(project.command(&toolchain).env("CADE_DISABLE", "1"));
+    run(project.command(&toolchain).env("CADE_READONLY", "1"));
+    assert_eq!(toolchain.invocations(), 2);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 3);
+    run(project.command(&toolchain).env("CADE_READONLY", "1"));
+    assert_eq!(toolchain.invocations(), 3);
+
+    // recaching replaces the existing entry
+    fs::write(toolchain.gcc(), FAKE_GCC.replace("echo \"compiling $src\"", "echo \"recompiling $src\"")).unwrap();
+    run(project.command(&toolchain).env("CADE_RECACHE", "true"));
+    assert_eq!(toolchain.invocations(), 4);
+    let hit = project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 4);
+    assert!(String::from_utf8(hit.stdout).unwrap().starts_with("recompiling"));
+}
+
+#[test]
+fn test_env_cache_dir() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    fs::remove_file(project.path(".cade")).unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+
+    run(project.command(&toolchain).env("CADE_CACHE_DIR", cache_dir.path()));
+    run(project.command(&toolchain).env("CADE_CACHE_DIR", cache_dir.path()));
+    assert_eq!(toolchain.invocations(), 1);
+    assert!(cache_dir.path().join("obj").is_dir());
+}
+
+#[test]
+fn test_exclude_rules() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, r#", "exclude": ["**/src/main.c"], "debug": true"#);
+
+    let output = project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(String::from_utf8(output.stdout).unwrap().contains("matches exclude rule '**/src/main.c'"));
+
+    let project = toolchain.project(false, r#", "exclude_args": ["-DNO_CACHE*"]"#);
+    run(project.command(&toolchain).arg("-DNO_CACHE=1"));
+    run(project.command(&toolchain).arg("-DNO_CACHE=1"));
+    assert_eq!(toolchain.invocations(), 4);
+    project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 5);
+
+    // options read from response files are checked as well
+    fs::write(project.path("build/args.rsp"), "-DNO_CACHE=1\n").unwrap();
+    run(project.command(&toolchain).arg(format!("@{}", project.path("build/args.rsp").display())));
+    run(project.command(&toolchain).arg(format!("@{}", project.path("build/args.rsp").display())));
+    assert_eq!(toolchain.invocations(), 7);
+}
+
+#[test]
+fn test_overrides() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, r#", "overrides": [
+        { "compiler": "tasking", "config": { "disable": true } },
+        { "source": "**/src/*.c", "config": { "readonly": true } }
+    ]"#);
+
+    // only the readonly override matches the gcc call
+    project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+}
+
+#[test]
+fn test_path_map() {
+    // the same toolchain installed at two locations
+    let first_toolchain = Toolchain::new();
+    let second_toolchain = Toolchain::new();
+    let toolchain_map = |toolchain: &Toolchain| format!(r#", "path_map": {{ "TOOLCHAIN": "{}" }}"#, toolchain.dir.path().display());
+    let first = first_toolchain.project(true, &toolchain_map(&first_toolchain));
+    let second = second_toolchain.project_with_cache(first_toolchain.cache.path(), true, &toolchain_map(&second_toolchain));
+
+    first.compile(&first_toolchain);
+    second.compile(&second_toolchain);
+    assert_eq!(first_toolchain.invocations(), 1);
+    assert_eq!(second_toolchain.invocations(), 0);
+    assert!(read(&second.path("build/main.d")).contains(second.path("src/main.h").to_str().unwrap()));
+}
+
+#[test]
+fn test_debug_prefix_map() {
+    let toolchain = Toolchain::new();
+    let first = toolchain.project(true, "");
+    let second = toolchain.project(true, "");
+
+    first.compile(&toolchain);
+    assert!(toolchain.invocation_log().contains(&format!("-fdebug-prefix-map={}=.", first.dir.path().display())));
+    // the injected option does not prevent hits in other checkouts
+    second.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 1);
+
+    // named paths are mapped as well, after the base dir containing them
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(true, "");
+    let sdk = project.path("sdk");
+    fs::write(project.path(".cade"), read(&project.path(".cade")).replacen('{', &format!(r#"{{ "path_map": {{ "SDK": "{}" }},"#, sdk.display()), 1)).unwrap();
+    project.compile(&toolchain);
+    let log = toolchain.invocation_log();
+    let base_dir_map = log.find(&format!("-fdebug-prefix-map={}=.", project.dir.path().display())).unwrap();
+    assert!(log.find(&format!("-fdebug-prefix-map={}=/SDK", sdk.display())).unwrap() > base_dir_map);
+
+    // nothing is injected if the call already remaps paths or the option is disabled
+    let toolchain = Toolchain::new();
+    run(toolchain.project(true, "").command(&toolchain).arg("-ffile-prefix-map=/a=/b"));
+    toolchain.project(true, r#", "debug_prefix_map": false"#).compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(!toolchain.invocation_log().contains("-fdebug-prefix-map"));
+}
+
+#[test]
+fn test_multiple_dependency_sets() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    fs::write(project.path("src/a.h"), "#define A\n").unwrap();
+    fs::write(project.path("src/b.h"), "#define B\n").unwrap();
+    let include = |header: &str| fs::write(project.path("src/main.h"), format!("#include \"{}\"\n", header)).unwrap();
+
+    // switching between two configurations including different headers
+    include("a.h");
+    project.compile(&toolchain);
+    include("b.h");
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+
+    include("a.h");
+    project.compile(&toolchain);
+    include("b.h");
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+    assert!(read(&project.path("build/main.d")).contains("b.h"));
+    assert_eq!(read(&project.path("build/main.o")), read(&project.path("src/main.c")) + "#include \"b.h\"\n#define B\n");
+}
+
+#[test]
+fn test_env_vars() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(true, r#", "env_vars": ["MY_FLAGS"]"#);
+
+    project.compile(&toolchain);
+    // variables known for gcc and configured ones are part of the key
+    run(project.command(&toolchain).env("CPATH", "/opt/include"));
+    run(project.command(&toolchain).env("MY_FLAGS", "-O2"));
+    assert_eq!(toolchain.invocations(), 3);
+    run(project.command(&toolchain).env("CPATH", "/opt/include"));
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 3);
+
+    // mapped paths in the values do not prevent hits in other checkouts
+    let other = toolchain.project(true, r#", "env_vars": ["MY_FLAGS"]"#);
+    run(project.command(&toolchain).env("CPATH", project.path("include")));
+    run(other.command(&toolchain).env("CPATH", other.path("include")));
+    assert_eq!(toolchain.invocations(), 4);
+}
+
+#[test]
+fn test_sloppiness() {
+    // sources using time macros are not cached by default
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    fs::write(project.path("src/main.c"), "const char *built = __DATE__;\n").unwrap();
+    project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 2);
+
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, r#", "sloppiness": ["time_macros", "locale", "empty_include_dirs"]"#);
+    fs::write(project.path("src/main.c"), "const char *built = __DATE__;\n").unwrap();
+    fs::create_dir(project.path("empty")).unwrap();
+    project.compile(&toolchain);
+    run(project.command(&toolchain).env("LC_ALL", "de_DE.UTF-8"));
+    run(project.command(&toolchain).arg("-I").arg(project.path("empty")));
+    assert_eq!(toolchain.invocations(), 1);
+
+    // a non empty include directory is still part of the key
+    fs::write(project.path("empty/a.h"), "").unwrap();
+    run(project.command(&toolchain).arg("-I").arg(project.path("empty")));
+    assert_eq!(toolchain.invocations(), 2);
+}
+
+#[test]
+fn test_canonical_args() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, r#", "sloppiness": ["flag_order"]"#);
+    let compile = |out_dir: &str, flags: &[&str]| {
+        fs::create_dir_all(project.path(out_dir)).unwrap();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cade"));
+        command
+            .current_dir(project.path(""))
+            .env("XDG_CONFIG_HOME", toolchain.dir.path())
+            .env_remove("CADE_CONFIG")
+            .arg(toolchain.gcc())
+            .args(flags)
+            .arg("-c").arg(project.path("src/main.c"))
+            .arg("-o").arg(project.path(&format!("{}/main.o", out_dir)))
+            .arg("-MF").arg(project.path(&format!("{}/main.d", out_dir)));
+        run(&mut command);
+    };
+
+    // output locations are not part of the key, the restored dependency file names the new target
+    compile("build", &["-DA", "-DBC"]);
+    compile("other", &["-DA", "-DBC"]);
+    assert_eq!(toolchain.invocations(), 1);
+    assert!(read(&project.path("other/main.d")).starts_with(&format!("{}:", project.path("other/main.o").display())));
+    assert_eq!(read(&project.path("other/main.o")), read(&project.path("build/main.o")));
+    compile("other", &["-DA", "-DBC", "-MT", "custom"]);
+    assert_eq!(toolchain.invocations(), 1);
+    assert!(read(&project.path("other/main.d")).starts_with("custom:"));
+
+    // the boundaries between arguments are part of the key, their order is not with the flag_order sloppiness
+    compile("build", &["-DAB", "-DC"]);
+    assert_eq!(toolchain.invocations(), 2);
+    compile("build", &["-DBC", "-DA"]);
+    assert_eq!(toolchain.invocations(), 2);
+
+    // options for the same macro or warning keep their order, the last one wins
+    compile("build", &["-DA=1", "-DA=2"]);
+    assert_eq!(toolchain.invocations(), 3);
+    compile("build", &["-DA=2", "-DA=1"]);
+    assert_eq!(toolchain.invocations(), 4);
+    compile("build", &["-DA", "-UA"]);
+    compile("build", &["-UA", "-DA"]);
+    compile("build", &["-Wfoo", "-Wno-foo"]);
+    compile("build", &["-Wno-foo", "-Wfoo"]);
+    assert_eq!(toolchain.invocations(), 8);
+}
+
+#[test]
+fn test_time_macros() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+    fs::write(project.path("src/main.c"), "const char *built = __DATE__;\n").unwrap();
+
+    // gcc takes the time from SOURCE_DATE_EPOCH, so the source can be cached
+    run(project.command(&toolchain).env("SOURCE_DATE_EPOCH", "1700000000"));
+    run(project.command(&toolchain).env("SOURCE_DATE_EPOCH", "1700000000"));
+    assert_eq!(toolchain.invocations(), 1);
+
+    // TASKING ignores it
+    let cctc = toolchain.dir.path().join("cctc");
+    fs::copy(toolchain.gcc(), &cctc).unwrap();
+    for _ in 0..2 {
+        run(Command::new(env!("CARGO_BIN_EXE_cade"))
+            .current_dir(project.path(""))
+            .env("XDG_CONFIG_HOME", toolchain.dir.path())
+            .env_remove("CADE_CONFIG")
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .arg(&cctc)
+            .arg("-c").arg(project.path("src/main.c"))
+            .arg("-o").arg(project.path("build/main.o"))
+            .arg(format!("--dep-file={}", project.path("build/main.d").display())));
+    }
+    assert_eq!(toolchain.invocations(), 3);
+}
+
+#[test]
+fn test_side_output_env_vars() {
+    let toolchain = Toolchain::new();
+    let project = toolchain.project(false, "");
+
+    // gcc writes a further dependency file, which a hit would not restore
+    for _ in 0..2 {
+        run(project.command(&toolchain).env("DEPENDENCIES_OUTPUT", project.path("build/extra.d")));
+    }
+    assert_eq!(toolchain.invocations(), 2);
+    project.compile(&toolchain);
+    project.compile(&toolchain);
+    assert_eq!(toolchain.invocations(), 3);
+}